@@ -18,4 +18,8 @@ fn main() {
     println!("cargo:rustc-link-search=native={}", out_dir);
     // but don't link it in!
     // we should only link it when we need it
+
+    if env::var_os("CARGO_FEATURE_SERVER").is_some() {
+        println!("cargo:rustc-link-lib=jackserver");
+    }
 }
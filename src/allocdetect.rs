@@ -0,0 +1,176 @@
+//! This module contains a global allocator wrapper which detects allocations made from the
+//! process callback. It is only available with the `alloc-detector` feature.
+//!
+//! Allocating (or freeing) memory in the process callback can take an unbounded amount of time
+//! and cause xruns. To catch handlers which do this, install the `DetectingAllocator` as the
+//! global allocator:
+//!
+//! ```no_run
+//! use easyjack::allocdetect::DetectingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOC: DetectingAllocator = DetectingAllocator::new();
+//! # fn main() {}
+//! ```
+//!
+//! While the process callback is running, every allocation and deallocation made on the JACK
+//! thread is counted as a violation. The count can be read with `Client::alloc_violations`, and
+//! `Client::set_panic_on_alloc` makes the process callback panic at the end of any cycle which
+//! allocated instead. The allocator itself never panics; without it installed, no violations are
+//! ever recorded.
+//!
+//! For unit tests, `assert_no_alloc` and `count_allocs` check a closure directly.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+thread_local! {
+    // the counter for the code currently being watched on this thread, or null
+    static CURRENT: Cell<*const AtomicUsize> = const { Cell::new(ptr::null()) };
+}
+
+fn record() {
+    // try_with fails during thread teardown, when nothing is being watched anyway
+    let _ = CURRENT.try_with(|c| {
+        let counter = c.get();
+        if !counter.is_null() {
+            unsafe { (*counter).fetch_add(1, Ordering::Relaxed) };
+        }
+    });
+}
+
+/// A global allocator which records allocations made while the process callback is running.
+/// All allocation is delegated to the wrapped allocator (`System` by default).
+pub struct DetectingAllocator<A = System> {
+    inner: A,
+}
+
+impl DetectingAllocator<System> {
+    pub const fn new() -> Self {
+        DetectingAllocator { inner: System }
+    }
+}
+
+impl<A> DetectingAllocator<A> {
+    /// Wraps a different allocator
+    pub const fn wrapping(inner: A) -> Self {
+        DetectingAllocator { inner: inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DetectingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record();
+        self.inner.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record();
+        self.inner.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        record();
+        self.inner.dealloc(p, layout)
+    }
+
+    unsafe fn realloc(&self, p: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record();
+        self.inner.realloc(p, layout, new_size)
+    }
+}
+
+/// The violation count and mode for one client, shared between the client and its process
+/// callback
+#[doc(hidden)]
+pub struct AllocWatch {
+    violations: AtomicUsize,
+    panic:      AtomicBool,
+}
+
+impl AllocWatch {
+    pub fn new() -> Self {
+        AllocWatch {
+            violations: AtomicUsize::new(0),
+            panic:      AtomicBool::new(false),
+        }
+    }
+
+    pub fn violations(&self) -> usize {
+        self.violations.load(Ordering::Relaxed)
+    }
+
+    pub fn set_panic(&self, panic: bool) {
+        self.panic.store(panic, Ordering::Relaxed)
+    }
+}
+
+// restores the previously watched counter, even if the watched code panics
+struct Restore(*const AtomicUsize);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.0));
+    }
+}
+
+/// Runs `f`, counting any allocations it makes on this thread. If the watch is in panic mode and
+/// `f` allocated, panics after `f` returns.
+#[doc(hidden)]
+pub fn run_watched<R, F: FnOnce() -> R>(watch: &AllocWatch, f: F) -> R {
+    let before = watch.violations();
+    let r = {
+        let _restore = Restore(CURRENT.with(|c| c.replace(&watch.violations)));
+        f()
+    };
+
+    let n = watch.violations().wrapping_sub(before);
+    if n > 0 && watch.panic.load(Ordering::Relaxed) {
+        panic!("{} allocations or deallocations in the process callback", n);
+    }
+
+    r
+}
+
+/// Runs `f` and returns its result along with the number of allocations and deallocations it
+/// made on this thread.
+pub fn count_allocs<R, F: FnOnce() -> R>(f: F) -> (R, usize) {
+    let watch = AllocWatch::new();
+    let r = run_watched(&watch, f);
+    (r, watch.violations())
+}
+
+/// Runs `f`, panicking if it allocates or deallocates on this thread.
+pub fn assert_no_alloc<R, F: FnOnce() -> R>(f: F) -> R {
+    let watch = AllocWatch::new();
+    watch.set_panic(true);
+    run_watched(&watch, f)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOC: DetectingAllocator = DetectingAllocator::new();
+
+    #[test]
+    fn counts_allocations() {
+        let mut v: Vec<u32> = Vec::with_capacity(4);
+        let (_, n) = count_allocs(|| v.push(1));
+        assert_eq!(n, 0);
+
+        let (_, n) = count_allocs(|| drop(vec![1, 2, 3]));
+        assert_eq!(n, 2);
+
+        assert_eq!(assert_no_alloc(|| v[0] + 1), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn assert_panics() {
+        assert_no_alloc(|| Box::new(5));
+    }
+}
@@ -0,0 +1,131 @@
+use std::slice;
+
+use callbackhandler::*;
+use port::*;
+use types::*;
+
+enum BusPorts {
+    Input(Vec<InputPortHandle<DefaultAudioSample>>),
+    Output(Vec<OutputPortHandle<DefaultAudioSample>>),
+}
+
+/// A group of audio ports which are registered together and always move in the same direction
+/// (a stereo pair, an 8 channel bus, etc).
+///
+/// An `AudioBus` is created with `Client::register_audio_bus`. In the process callback, all of
+/// the buffers of the bus can be fetched at once with `read_buffers` (for input buses) or
+/// `write_buffers` (for output buses). Fetching the buffers does not allocate.
+pub struct AudioBus {
+    ports: BusPorts,
+
+    // storage for the views handed out by read_buffers and write_buffers.
+    // The capacity is reserved up front so that filling this in the process callback never
+    // allocates. The pointers are only valid until the end of the cycle in which they were set.
+    views: Vec<*mut [DefaultAudioSample]>,
+}
+
+impl AudioBus {
+    #[doc(hidden)]
+    pub fn new(ports: Vec<UnknownPortHandle>, direction: PortDirection) -> Self {
+        let n = ports.len();
+        let ports = match direction {
+            PortDirection::Input => BusPorts::Input(
+                ports.into_iter().map(|p| unsafe { p.force_as_input() }).collect()),
+
+            PortDirection::Output => BusPorts::Output(
+                ports.into_iter().map(|p| unsafe { p.force_as_output() }).collect()),
+        };
+
+        AudioBus {
+            ports: ports,
+            views: Vec::with_capacity(n),
+        }
+    }
+
+    /// Returns the number of channels in the bus
+    pub fn channels(&self) -> usize {
+        match self.ports {
+            BusPorts::Input(ref p)  => p.len(),
+            BusPorts::Output(ref p) => p.len(),
+        }
+    }
+
+    /// Returns the direction of all of the ports in the bus
+    pub fn direction(&self) -> PortDirection {
+        match self.ports {
+            BusPorts::Input(_)  => PortDirection::Input,
+            BusPorts::Output(_) => PortDirection::Output,
+        }
+    }
+
+    /// Returns the handles of the ports in an input bus, in channel order.
+    /// Returns None if this is an output bus.
+    pub fn input_ports(&self) -> Option<&[InputPortHandle<DefaultAudioSample>]> {
+        match self.ports {
+            BusPorts::Input(ref p) => Some(p),
+            BusPorts::Output(_)    => None,
+        }
+    }
+
+    /// Returns the handles of the ports in an output bus, in channel order.
+    /// Returns None if this is an input bus.
+    pub fn output_ports(&self) -> Option<&[OutputPortHandle<DefaultAudioSample>]> {
+        match self.ports {
+            BusPorts::Input(_)      => None,
+            BusPorts::Output(ref p) => Some(p),
+        }
+    }
+
+    /// Gets the readable buffers of every channel of an input bus, in channel order.
+    ///
+    /// Panics if this is an output bus.
+    pub fn read_buffers<'a>(&'a mut self, nframes: NumFrames, ctx: &'a CallbackContext)
+        -> &'a [&'a [DefaultAudioSample]]
+    {
+        self.views.clear();
+        match self.ports {
+            BusPorts::Input(ref ports) => {
+                for p in ports {
+                    let buf = p.get_read_buffer(nframes, ctx);
+                    self.views.push(buf as *const [DefaultAudioSample] as *mut _);
+                }
+            },
+
+            BusPorts::Output(_) => panic!("read_buffers called on an output bus"),
+        }
+
+        // raw slice pointers have the same layout as slice references
+        unsafe {
+            slice::from_raw_parts(
+                self.views.as_ptr() as *const &'a [DefaultAudioSample],
+                self.views.len())
+        }
+    }
+
+    /// Gets the writable buffers of every channel of an output bus, in channel order.
+    ///
+    /// Panics if this is an input bus.
+    pub fn write_buffers<'a>(&'a mut self, nframes: NumFrames, ctx: &'a CallbackContext)
+        -> &'a mut [&'a mut [DefaultAudioSample]]
+    {
+        self.views.clear();
+        match self.ports {
+            BusPorts::Input(_) => panic!("write_buffers called on an input bus"),
+
+            BusPorts::Output(ref ports) => {
+                for p in ports {
+                    let buf = p.get_write_buffer(nframes, ctx);
+                    self.views.push(buf as *mut [DefaultAudioSample]);
+                }
+            },
+        }
+
+        // raw slice pointers have the same layout as slice references, and each channel is a
+        // distinct port buffer so handing out mutable references to all of them is okay
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.views.as_mut_ptr() as *mut &'a mut [DefaultAudioSample],
+                self.views.len())
+        }
+    }
+}
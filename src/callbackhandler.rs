@@ -3,15 +3,54 @@
 //! marker constraints because the client always takes ownership of the callback Handlers, ensuring
 //! that the callbacks will only be called in a thread safe manner
 
+use ffi;
+use jack_sys;
+use libc;
+
+use std::ptr;
+
+use metadata::*;
+use midi::MidiBuffer;
+use offline::*;
 use types::*;
 
 /// the CallbackContext is passed to some callback handlers and used by some methods to maintain
 /// some context and control lifetimes during callbacks
-pub struct CallbackContext {}
+pub struct CallbackContext {
+    // the ports of the OfflineHost running this cycle, or null during a real JACK cycle
+    host: *mut HostPorts,
+}
 
 impl CallbackContext {
     #[doc(hidden)]
-    pub fn new() -> Self { CallbackContext { } }
+    pub fn new() -> Self { CallbackContext { host: ptr::null_mut() } }
+
+    pub(crate) fn offline(host: *mut HostPorts) -> Self { CallbackContext { host: host } }
+
+    /// Returns the buffer for the port this cycle, from JACK or from the offline host
+    pub(crate) unsafe fn port_buffer(&self, port: *mut jack_sys::jack_port_t, nframes: NumFrames)
+        -> *mut libc::c_void
+    {
+        if self.host.is_null() {
+            assert!(!is_host_port(port), "port belongs to an OfflineHost, not to JACK");
+            ffi::jack_port_get_buffer(port, nframes)
+        } else {
+            (*self.host).buffer(port)
+        }
+    }
+
+    /// Returns the offline host's midi buffer for the port, or null during a real JACK cycle.
+    ///
+    /// The pointer stays valid until the end of the cycle. Several may be handed out for the same
+    /// port, so only dereference it for the length of a single call, as is done with JACK's own
+    /// midi buffers.
+    pub(crate) unsafe fn host_midi(&self, port: *mut jack_sys::jack_port_t) -> *mut MidiBuffer {
+        if self.host.is_null() {
+            ptr::null_mut()
+        } else {
+            (*self.host).midi(port)
+        }
+    }
 }
 
 /// This trait defines a handler for the process callback
@@ -19,6 +58,22 @@ pub trait ProcessHandler {
     fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32;
 }
 
+/// This trait defines a handler which is run on each thread JACK creates for the client (in
+/// practice, the process thread) before the thread does anything else. Use it for per-thread
+/// setup, such as setting CPU flags or pinning the thread to a core. See `ThreadSetup` for some
+/// common setup steps.
+///
+/// Any `FnMut()` closure can be used as a handler.
+pub trait ThreadInitHandler {
+    fn thread_init(&mut self);
+}
+
+impl<F: FnMut()> ThreadInitHandler for F {
+    fn thread_init(&mut self) {
+        self()
+    }
+}
+
 /// This trait defines the callbacks which may be delivered to the metadata thread
 pub trait MetadataHandler {
     /// Called when the sample rate is changed
@@ -31,6 +86,35 @@ pub trait MetadataHandler {
 
     fn on_xrun(&mut self) -> i32 { 0 }
 
+    /// Called when the server shuts down or disconnects the client. No more callbacks will be
+    /// delivered, and the only thing left to do with the client is close it.
+    fn on_shutdown(&mut self) { }
+
+    /// Called when the server enters (`starting` is true) or leaves freewheel mode. While
+    /// freewheeling, the process callback is run as fast as possible instead of in step with the
+    /// audio hardware.
+    #[allow(unused_variables)]
+    fn on_freewheel(&mut self, starting: bool) { }
+
+    /// Called when the server needs to recompute latencies. `mode` indicates which direction is
+    /// being recomputed.
+    ///
+    /// Clients which introduce latency between their inputs and outputs (internal delay lines, for
+    /// example) should look at the latency of the ports on one side (`get_latency_range`), add
+    /// their own latency, then set the result on the ports on the other side
+    /// (`set_latency_range`). For `LatencyMode::Capture`, read the inputs and set the outputs. For
+    /// `LatencyMode::Playback`, read the outputs and set the inputs.
+    ///
+    /// Clients which do not implement this callback get a default behavior from JACK which assumes
+    /// that the client adds no latency.
+    #[allow(unused_variables)]
+    fn on_latency(&mut self, mode: LatencyMode) { }
+
+    /// Called when a metadata property is created, changed, or deleted by any client. `key` is
+    /// `None` when all of the properties of `subject` were removed at once.
+    #[allow(unused_variables)]
+    fn on_property_change(&mut self, subject: UUID, key: Option<&str>, change: PropertyChange) { }
+
     /// Function must return all the types of callbacks it wishes to be given
     fn callbacks_of_interest(&self) -> Vec<MetadataHandlers>;
 }
@@ -46,4 +130,6 @@ pub enum MetadataHandlers {
     PortRename,
     GraphOrder,
     Xrun,
+    Latency,
+    PropertyChange,
 }
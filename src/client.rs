@@ -1,12 +1,26 @@
+use ffi;
 use jack_sys;
 use libc;
 
 use std::ffi::{CString, CStr};
+use std::ptr;
+use std::sync::Arc;
+use std::time::Instant;
 
+#[cfg(feature = "alloc-detector")]
+use allocdetect::{self, AllocWatch};
+
+use bus::*;
 use callbackhandler::*;
+use cycle::*;
+use internal::*;
+use metadata::*;
 use midi::*;
 use port::*;
+use stats::*;
 use types::*;
+use uuid::*;
+use watchdog::*;
 
 /// A jack client connected to a jack server
 ///
@@ -14,13 +28,113 @@ use types::*;
 pub struct Client<'a> {
     c_client: *mut jack_sys::jack_client_t,
 
+    // false if the client belongs to someone else (the server, for an internal client written
+    // with `internal_client!`) and must not be closed by us
+    owned: bool,
+
     // store the handlers in a box so that we can store a trait object + take ownership
     // I do not like boxing everything up because it causes unnecessary heap allocation :(
     process_handler:  Option<Box<ProcessHandler + 'a>>,
-    metadata_handler: Option<Box<MetadataHandler + 'a>>
+    metadata_handler: Option<Box<MetadataHandler + 'a>>,
+    thread_init_handler: Option<Box<dyn ThreadInitHandler + 'a>>,
+    process_thread:   Option<Box<ProcessThreadState<'a>>>,
+
+    stats: Arc<StatsCollector>,
+
+    // true once the metadata handler has taken over the xrun callback
+    xrun_callback_set: bool,
+
+    #[cfg(feature = "alloc-detector")]
+    alloc_watch: Arc<AllocWatch>,
+}
+
+// the process handler, along with anything else the process callback needs access to
+struct ProcessState<T> {
+    handler: T,
+    client:  *mut jack_sys::jack_client_t,
+    stats:   Arc<StatsCollector>,
+
+    #[cfg(feature = "alloc-detector")]
+    alloc_watch: Arc<AllocWatch>,
+}
+
+impl<T: ProcessHandler> ProcessHandler for ProcessState<T> {
+    fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+        #[cfg(feature = "alloc-detector")]
+        {
+            let handler = &mut self.handler;
+            return allocdetect::run_watched(&self.alloc_watch, || handler.process(ctx, nframes));
+        }
+
+        #[cfg(not(feature = "alloc-detector"))]
+        self.handler.process(ctx, nframes)
+    }
+}
+
+// the function run by a process thread, along with the client it belongs to
+struct ProcessThreadState<'a> {
+    handler: Box<dyn FnMut(&mut CycleLoop) + 'a>,
+    client:  *mut jack_sys::jack_client_t,
+}
+
+// the metadata handler, along with anything else the metadata callbacks need access to
+struct MetadataState<T> {
+    handler: T,
+    stats:   Arc<StatsCollector>,
+}
+
+impl<T: MetadataHandler> MetadataHandler for MetadataState<T> {
+    fn sample_rate_changed(&mut self, srate: NumFrames) -> i32 {
+        self.handler.sample_rate_changed(srate)
+    }
+
+    fn on_port_connect(&mut self, a: PortId, b: PortId, status: PortConnectStatus) {
+        self.handler.on_port_connect(a, b, status)
+    }
+
+    fn on_xrun(&mut self) -> i32 {
+        self.stats.record_client_xrun();
+        self.handler.on_xrun()
+    }
+
+    fn on_shutdown(&mut self) {
+        self.handler.on_shutdown()
+    }
+
+    fn on_freewheel(&mut self, starting: bool) {
+        self.handler.on_freewheel(starting)
+    }
+
+    fn on_latency(&mut self, mode: LatencyMode) {
+        self.handler.on_latency(mode)
+    }
+
+    fn on_property_change(&mut self, subject: UUID, key: Option<&str>, change: PropertyChange) {
+        self.handler.on_property_change(subject, key, change)
+    }
+
+    fn callbacks_of_interest(&self) -> Vec<MetadataHandlers> {
+        self.handler.callbacks_of_interest()
+    }
 }
 
 impl<'a> Client<'a> {
+    fn wrap(cl: *mut jack_sys::jack_client_t, owned: bool) -> Self {
+        Client {
+            c_client:          cl,
+            owned:             owned,
+            process_handler:   None,
+            metadata_handler:  None,
+            thread_init_handler: None,
+            process_thread:    None,
+            stats:             Arc::new(StatsCollector::new(cl)),
+            xrun_callback_set: false,
+
+            #[cfg(feature = "alloc-detector")]
+            alloc_watch:       Arc::new(AllocWatch::new()),
+        }
+    }
+
     fn open_helper(cl: *mut jack_sys::jack_client_t, status: u32, name: &str)
         -> Result<(Self, String), status::Status>
     {
@@ -28,11 +142,7 @@ impl<'a> Client<'a> {
         if cl.is_null() {
             Err(status)
         } else {
-            let cl = Client {
-                c_client:          cl,
-                process_handler:   None,
-                metadata_handler:  None,
-            };
+            let cl = Client::wrap(cl, true);
 
             let name = if status.contains(status::NAME_NOT_UNIQUE) {
                 cl.get_name()
@@ -52,6 +162,8 @@ impl<'a> Client<'a> {
     /// be given, Err will be returned. Otherwise Returns the client and the name assigned to the
     /// client.
     ///
+    /// With the `dlopen` feature, a missing libjack is reported as `status::LIBRARY_NOT_FOUND`.
+    ///
     /// TODO client_name_size details in docs and in code
     pub fn open(name: &str, opts: options::Options) -> Result<(Self, String), status::Status> {
         // TODO does jack check if the options are valid?
@@ -61,7 +173,7 @@ impl<'a> Client<'a> {
         let mut status = 0 as jack_sys::jack_status_t;
         let statusptr  = &mut status as *mut jack_sys::jack_status_t;
 
-        let cl = unsafe { jack_sys::jack_client_open(cstr.as_ptr(), opts.bits(), statusptr) };
+        let cl = unsafe { ffi::client_open(&cstr, opts.bits(), statusptr, None) };
         Client::open_helper(cl, status, name)
     }
 
@@ -79,16 +191,19 @@ impl<'a> Client<'a> {
 
         let additionalopts = options::Options::from_bits(jack_sys::JackServerName).unwrap();
         let cl = unsafe {
-            jack_sys::jack_client_open(
-                cstr.as_ptr(),
-                (opts | additionalopts).bits(),
-                statusptr,
-                sstr.as_ptr())
+            ffi::client_open(&cstr, (opts | additionalopts).bits(), statusptr, Some(&sstr))
         };
 
         Client::open_helper(cl, status, clientname)
     }
 
+    /// Wraps a client which was opened by someone else, such as the client the server passes to
+    /// an internal client's `jack_initialize`. The returned client can not be closed.
+    #[doc(hidden)]
+    pub unsafe fn from_unowned(cl: *mut jack_sys::jack_client_t) -> Self {
+        Client::wrap(cl, false)
+    }
+
     /// Returns the actual name of the client. This is useful when
     /// USE_EXACT_NAME is not specified, because the jack server might assign
     /// some other name to your client to ensure that it is unique.
@@ -99,11 +214,69 @@ impl<'a> Client<'a> {
         // do not need to free the string
         unsafe {
             let raw = self.c_client;
-            let cstr = jack_sys::jack_get_client_name(raw);
+            let cstr = ffi::jack_get_client_name(raw);
             String::from(CStr::from_ptr(cstr).to_str().unwrap())
         }
     }
 
+    /// Returns the sample rate of the server, in frames per second
+    pub fn get_sample_rate(&self) -> NumFrames {
+        unsafe { ffi::jack_get_sample_rate(self.c_client) }
+    }
+
+    /// Returns the current period size of the server, in frames
+    pub fn get_buffer_size(&self) -> NumFrames {
+        unsafe { ffi::jack_get_buffer_size(self.c_client) }
+    }
+
+    /// Returns true if the server is running with realtime scheduling
+    pub fn is_realtime(&self) -> bool {
+        unsafe { ffi::jack_is_realtime(self.c_client) != 0 }
+    }
+
+    /// Returns the realtime priority of the client's process thread, or None if the server is not
+    /// running with realtime scheduling
+    pub fn real_time_priority(&self) -> Option<i32> {
+        let p = unsafe { ffi::jack_client_real_time_priority(self.c_client) };
+        if p < 0 { None } else { Some(p) }
+    }
+
+    /// Returns the highest realtime priority a thread created for this client may be given, or
+    /// None if the server is not running with realtime scheduling
+    pub fn max_real_time_priority(&self) -> Option<i32> {
+        let p = unsafe { ffi::jack_client_max_real_time_priority(self.c_client) };
+        if p < 0 { None } else { Some(p) }
+    }
+
+    /// Returns the UUID of this client, or None if the server did not assign one.
+    pub fn uuid(&self) -> Option<UUID> {
+        let s = unsafe { take_jack_string(ffi::jack_client_get_uuid(self.c_client)) };
+        s.and_then(|s| uuid_parse(&s))
+    }
+
+    /// Looks up the UUID of the client with the given name. Returns None if there is no such
+    /// client.
+    ///
+    /// UUIDs do not change when a client is renamed, so they are a more reliable way to refer to
+    /// other clients (for example, as the subject of metadata properties).
+    pub fn uuid_for_client_name(&self, name: &str) -> Option<UUID> {
+        let cstr = CString::new(name).unwrap();
+        let s = unsafe {
+            take_jack_string(ffi::jack_get_uuid_for_client_name(self.c_client, cstr.as_ptr()))
+        };
+
+        s.and_then(|s| uuid_parse(&s))
+    }
+
+    /// Looks up the name of the client with the given UUID. Returns None if there is no such
+    /// client.
+    pub fn client_name_by_uuid(&self, uuid: UUID) -> Option<String> {
+        let cstr = CString::new(uuid_unparse(uuid)).unwrap();
+        unsafe {
+            take_jack_string(ffi::jack_get_client_name_by_uuid(self.c_client, cstr.as_ptr()))
+        }
+    }
+
     /// Create a new port for this client. Ports are used to move data in and out of the client
     /// (audio data, midi data, etc). Ports may be connected to other ports in various ways.
     ///
@@ -111,32 +284,46 @@ impl<'a> Client<'a> {
     /// The port's full name contains the name of the client, followed by a colon (:), followed by
     /// the port's short name.
     ///
-    /// All ports have a type. The `port_type` module contains port types which may be used.
+    /// All ports have a type. The `port_type` module contains the types built into JACK, but any
+    /// string may be used as a type (for example "32 bit float RGBA video"). Ports may only be
+    /// connected to ports of the same type.
+    ///
+    /// For the built in types `buffer_size` is ignored, JACK already knows how large their buffers
+    /// are. For any other type, `buffer_size` gives the size (in bytes) of the port's buffer and
+    /// must not be zero.
     ///
     /// You may also specify a number of flags from the `port_flags` module which control the
     /// behavior of the created port (input vs output, etc)
     ///
-    /// This function has to figure out what kind of port to return based on the flags provided.
+    /// This function cannot know what kind of port it has created, so it returns an
+    /// `UnknownPortHandle`. See `register_input_port` and `register_output_port` for typed
+    /// versions.
     ///
-    /// TODO something about buffer size I haven't figured out yet
     /// TODO port_name_size()
-    fn register_port(
+    pub fn register_port(
         &mut self,
         name: &str,
         ptype: PortType,
-        opts: port_flags::PortFlags)
+        opts: port_flags::PortFlags,
+        buffer_size: usize)
         -> Result<UnknownPortHandle, status::Status>
     {
+        let builtin =
+            ptype == port_type::DEFAULT_AUDIO_TYPE || ptype == port_type::DEFAULT_MIDI_TYPE;
+        if !builtin && buffer_size == 0 {
+            return Err(status::INVALID_OPTION);
+        }
+
         let cstr = CString::new(name).unwrap();
         let typestr = CString::new(ptype).unwrap();
 
         let port = unsafe {
-            jack_sys::jack_port_register(
+            ffi::jack_port_register(
                 self.c_client,
                 cstr.as_ptr(),
                 typestr.as_ptr(),
                 opts.bits() as ::libc::c_ulong,
-                0)
+                if builtin { 0 } else { buffer_size as ::libc::c_ulong })
         };
 
         if port.is_null() {
@@ -147,40 +334,145 @@ impl<'a> Client<'a> {
         }
     }
 
-    /// Helper function which registers an input audio port with a given name.
-    pub fn register_input_audio_port(&mut self, name: &str)
-            -> Result<InputPortHandle<DefaultAudioSample>, status::Status>
+    /// Registers an input port of any type, returning a handle typed by `SampleType`.
+    ///
+    /// The type of the port is `SampleType::PORT_TYPE` (see `PortSample`), so the handle always
+    /// matches the port.
+    ///
+    /// `extra_flags` may contain any of the port flags other than `PORT_IS_INPUT` and
+    /// `PORT_IS_OUTPUT` (for example `PORT_IS_TERMINAL` or `PORT_IS_PHYSICAL`). See
+    /// `register_port` for a description of the other arguments.
+    pub fn register_input_port<SampleType: PortSample>(
+        &mut self,
+        name: &str,
+        extra_flags: port_flags::PortFlags,
+        buffer_size: usize)
+        -> Result<InputPortHandle<SampleType>, status::Status>
     {
+        if extra_flags.intersects(port_flags::PORT_IS_INPUT | port_flags::PORT_IS_OUTPUT) {
+            return Err(status::INVALID_OPTION);
+        }
+
         let p = self.register_port(
             name,
-            port_type::DEFAULT_AUDIO_TYPE,
-            port_flags::PORT_IS_INPUT);
+            SampleType::PORT_TYPE,
+            port_flags::PORT_IS_INPUT | extra_flags,
+            buffer_size);
 
-        p.map(|p| unsafe { p.force_as_input::<DefaultAudioSample>() })
+        p.map(|p| unsafe { p.force_as_input::<SampleType>() })
     }
 
-    /// Helper function which registers an input midi port with a given name.
-    pub fn register_input_midi_port(&mut self, name: &str)
-            -> Result<InputPortHandle<MidiEvent>, status::Status>
+    /// Registers an output port of any type, returning a handle typed by `SampleType`.
+    ///
+    /// The type of the port is `SampleType::PORT_TYPE` (see `PortSample`), so the handle always
+    /// matches the port.
+    ///
+    /// `extra_flags` may contain any of the port flags other than `PORT_IS_INPUT` and
+    /// `PORT_IS_OUTPUT` (for example `PORT_IS_TERMINAL` or `PORT_IS_PHYSICAL`). See
+    /// `register_port` for a description of the other arguments.
+    pub fn register_output_port<SampleType: PortSample>(
+        &mut self,
+        name: &str,
+        extra_flags: port_flags::PortFlags,
+        buffer_size: usize)
+        -> Result<OutputPortHandle<SampleType>, status::Status>
     {
+        if extra_flags.intersects(port_flags::PORT_IS_INPUT | port_flags::PORT_IS_OUTPUT) {
+            return Err(status::INVALID_OPTION);
+        }
+
         let p = self.register_port(
             name,
-            port_type::DEFAULT_MIDI_TYPE,
-            port_flags::PORT_IS_INPUT);
+            SampleType::PORT_TYPE,
+            port_flags::PORT_IS_OUTPUT | extra_flags,
+            buffer_size);
 
-        p.map(|p| unsafe { p.force_as_input::<MidiEvent>() })
+        p.map(|p| unsafe { p.force_as_output::<SampleType>() })
+    }
+
+    /// Helper function which registers an input audio port with a given name.
+    pub fn register_input_audio_port(&mut self, name: &str)
+            -> Result<InputPortHandle<DefaultAudioSample>, status::Status>
+    {
+        self.register_input_port(name, port_flags::PortFlags::empty(), 0)
+    }
+
+    /// Helper function which registers an input midi port with a given name.
+    pub fn register_input_midi_port(&mut self, name: &str)
+            -> Result<InputPortHandle<MidiEvent>, status::Status>
+    {
+        self.register_input_port(name, port_flags::PortFlags::empty(), 0)
     }
 
     /// Helper function which registers an output audio port with a given name.
     pub fn register_output_audio_port(&mut self, name: &str)
             -> Result<OutputPortHandle<DefaultAudioSample>, status::Status>
     {
-        let p = self.register_port(
-            name,
-            port_type::DEFAULT_AUDIO_TYPE,
-            port_flags::PORT_IS_OUTPUT);
+        self.register_output_port(name, port_flags::PortFlags::empty(), 0)
+    }
 
-        p.map(|p| unsafe { p.force_as_output::<DefaultAudioSample>() })
+    /// Helper function which registers an output midi port with a given name.
+    pub fn register_output_midi_port(&mut self, name: &str)
+            -> Result<OutputPortHandle<MidiEvent>, status::Status>
+    {
+        self.register_output_port(name, port_flags::PortFlags::empty(), 0)
+    }
+
+    /// Registers a group of `channels` audio ports which all flow in the same `direction`.
+    ///
+    /// The ports are named `prefix_1`, `prefix_2`, and so on. Each port is tagged with the JACK
+    /// port group metadata property (`metadata_keys::PORT_GROUP`), with `prefix` as the group
+    /// name, so that other applications know the ports belong together.
+    ///
+    /// If any of the ports cannot be registered or tagged, the ports which were already registered
+    /// are unregistered and the error is returned.
+    pub fn register_audio_bus(&mut self, prefix: &str, channels: usize, direction: PortDirection)
+        -> Result<AudioBus, status::Status>
+    {
+        let flags = match direction {
+            PortDirection::Input  => port_flags::PORT_IS_INPUT,
+            PortDirection::Output => port_flags::PORT_IS_OUTPUT,
+        };
+
+        let mut ports = Vec::with_capacity(channels);
+        let mut res = Ok(());
+
+        for i in 0..channels {
+            let name = format!("{}_{}", prefix, i + 1);
+            let p = self.register_port(&name, port_type::DEFAULT_AUDIO_TYPE, flags, 0);
+
+            match p {
+                Ok(p)  => ports.push(p),
+                Err(e) => { res = Err(e); break; },
+            }
+        }
+
+        if res.is_ok() {
+            for p in &ports {
+                res = self.set_property(p.get_uuid(), metadata_keys::PORT_GROUP, prefix, None);
+                if res.is_err() {
+                    break;
+                }
+            }
+        }
+
+        match res {
+            Ok(()) => Ok(AudioBus::new(ports, direction)),
+            Err(e) => {
+                for p in ports {
+                    // already failing, nothing useful to do with a second error
+                    let _ = self.unregister_port(p);
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the size (in bytes) of the buffers used by ports of the given type
+    pub fn get_port_type_buffer_size(&self, ptype: PortType) -> usize {
+        let typestr = CString::new(ptype).unwrap();
+        unsafe { ffi::jack_port_type_get_buffer_size(self.c_client, typestr.as_ptr()) }
     }
 
     /// Removes the port from the client and invalidates the port and all
@@ -188,7 +480,7 @@ impl<'a> Client<'a> {
     ///
     /// The server disconnects everything that was previously connected to the port.
     pub fn unregister_port<T: Port>(&mut self, port: T) -> Result<(), status::Status> {
-        let ret = unsafe { jack_sys::jack_port_unregister(self.c_client, port.get_raw()) };
+        let ret = unsafe { ffi::jack_port_unregister(self.c_client, jack_port(&port)) };
 
         if ret == 0 {
             Ok(())
@@ -200,7 +492,7 @@ impl<'a> Client<'a> {
 
     pub fn get_port_by_name(&self, name: &str) -> Option<UnknownPortHandle> {
         let cstr = CString::new(name).unwrap();
-        let ptr = unsafe { jack_sys::jack_port_by_name(self.c_client, cstr.as_ptr()) };
+        let ptr = unsafe { ffi::jack_port_by_name(self.c_client, cstr.as_ptr()) };
 
         if ptr.is_null() {
             None
@@ -209,8 +501,51 @@ impl<'a> Client<'a> {
         }
     }
 
+    /// Looks up a port by its full name, falling back to searching the aliases of every port
+    /// known to the server if no port has the given name.
+    ///
+    /// The fallback is relatively expensive, it must ask the server for a list of all ports and
+    /// inspect the aliases of each of them.
+    pub fn get_port_by_name_or_alias(&self, name: &str) -> Option<UnknownPortHandle> {
+        if let Some(p) = self.get_port_by_name(name) {
+            return Some(p);
+        }
+
+        let names = unsafe {
+            ffi::jack_get_ports(self.c_client, ptr::null(), ptr::null(), 0)
+        };
+
+        if names.is_null() {
+            return None;
+        }
+
+        let mut found = None;
+        unsafe {
+            let mut i = 0;
+            while !(*names.offset(i)).is_null() {
+                let ptr = ffi::jack_port_by_name(self.c_client, *names.offset(i));
+                i += 1;
+
+                if ptr.is_null() {
+                    continue;
+                }
+
+                let port = UnknownPortHandle::new(ptr);
+                if port.aliases().iter().any(|a| a == name) {
+                    found = Some(port);
+                    break;
+                }
+            }
+
+            // the list was allocated by jack, so jack needs to free it
+            ffi::jack_free(names as *mut libc::c_void);
+        }
+
+        found
+    }
+
     pub fn get_port_by_id(&self, id: PortId) -> Option<UnknownPortHandle> {
-        let ptr = unsafe { jack_sys::jack_port_by_id(self.c_client, id) };
+        let ptr = unsafe { ffi::jack_port_by_id(self.c_client, id) };
 
         if ptr.is_null() {
             None
@@ -224,7 +559,7 @@ impl<'a> Client<'a> {
     /// perform lookups for the names before making the call
     pub fn connect_ports(&mut self, port1: &str, port2: &str) -> Result<(), status::Status> {
         let res = unsafe {
-            jack_sys::jack_connect(
+            ffi::jack_connect(
                 self.c_client,
                 CString::new(port1).unwrap().as_ptr(),
                 CString::new(port2).unwrap().as_ptr())
@@ -245,7 +580,7 @@ impl<'a> Client<'a> {
     /// perform lookups for the names before making the call
     pub fn disconnect_ports(&mut self, port1: &str, port2: &str) -> Result<(), status::Status> {
         let res = unsafe {
-            jack_sys::jack_disconnect(
+            ffi::jack_disconnect(
                 self.c_client,
                 CString::new(port1).unwrap().as_ptr(),
                 CString::new(port2).unwrap().as_ptr())
@@ -258,6 +593,214 @@ impl<'a> Client<'a> {
         }
     }
 
+    /// Sets a metadata property on the given subject (usually a port or client UUID, see
+    /// `Port::get_uuid`).
+    ///
+    /// `key` identifies the property, the `metadata_keys` module contains the well-known keys.
+    /// `ptype` is the type of the value, either a MIME type or a URI. If it is `None`, the value
+    /// is assumed to be a plain string.
+    pub fn set_property(
+        &mut self,
+        subject: UUID,
+        key: &str,
+        value: &str,
+        ptype: Option<&str>)
+        -> Result<(), status::Status>
+    {
+        let key   = CString::new(key).unwrap();
+        let value = CString::new(value).unwrap();
+        let ptype = ptype.map(|t| CString::new(t).unwrap());
+
+        let ret = unsafe {
+            ffi::jack_set_property(
+                self.c_client,
+                subject,
+                key.as_ptr(),
+                value.as_ptr(),
+                ptype.as_ref().map_or(ptr::null(), |t| t.as_ptr()))
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(status::FAILURE)
+        }
+    }
+
+    /// Gets a single metadata property of the given subject, if it is set.
+    pub fn get_property(&self, subject: UUID, key: &str) -> Option<Property> {
+        let ckey = CString::new(key).unwrap();
+        let mut value = ptr::null_mut();
+        let mut ptype = ptr::null_mut();
+
+        let ret = unsafe {
+            ffi::jack_get_property(subject, ckey.as_ptr(), &mut value, &mut ptype)
+        };
+
+        if ret != 0 {
+            return None;
+        }
+
+        // both strings were allocated by jack and must be freed by jack
+        unsafe {
+            Some(Property {
+                key:           key.to_string(),
+                value:         take_jack_string(value).unwrap_or_default(),
+                property_type: take_jack_string(ptype),
+            })
+        }
+    }
+
+    /// Gets all of the metadata properties of the given subject
+    pub fn get_properties(&self, subject: UUID) -> Vec<Property> {
+        let mut desc = jack_sys::jack_description_t::default();
+        let ret = unsafe { ffi::jack_get_properties(subject, &mut desc) };
+
+        if ret < 0 {
+            Vec::new()
+        } else {
+            unsafe { take_description(&mut desc) }
+        }
+    }
+
+    /// Gets all of the metadata properties of every subject known to the server
+    pub fn get_all_properties(&self) -> Vec<(UUID, Vec<Property>)> {
+        let mut descs = ptr::null_mut();
+        let cnt = unsafe { ffi::jack_get_all_properties(&mut descs) };
+
+        if cnt <= 0 || descs.is_null() {
+            return Vec::new();
+        }
+
+        unsafe {
+            let all = (0..cnt as isize)
+                .map(|i| {
+                    let desc = &mut *descs.offset(i);
+                    (desc.subject, take_description(desc))
+                })
+                .collect();
+
+            // the descriptions are freed individually, but the array holding them is one block
+            ffi::jack_free(descs as *mut libc::c_void);
+            all
+        }
+    }
+
+    /// Removes a single metadata property from the given subject
+    pub fn remove_property(&mut self, subject: UUID, key: &str) -> Result<(), status::Status> {
+        let key = CString::new(key).unwrap();
+        let ret = unsafe { ffi::jack_remove_property(self.c_client, subject, key.as_ptr()) };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(status::FAILURE)
+        }
+    }
+
+    /// Removes all of the metadata properties of the given subject. Returns the number of
+    /// properties removed.
+    pub fn remove_properties(&mut self, subject: UUID) -> Result<usize, status::Status> {
+        let ret = unsafe { ffi::jack_remove_properties(self.c_client, subject) };
+
+        if ret < 0 {
+            Err(status::FAILURE)
+        } else {
+            Ok(ret as usize)
+        }
+    }
+
+    /// Removes every metadata property of every subject known to the server. Use with care.
+    pub fn remove_all_properties(&mut self) -> Result<(), status::Status> {
+        let ret = unsafe { ffi::jack_remove_all_properties(self.c_client) };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(status::FAILURE)
+        }
+    }
+
+    /// Loads an internal client into the server.
+    ///
+    /// `name` is the name the new client should have. `load_name` is the name of the shared object
+    /// to load, and defaults to `name`. `load_init` is the initialization string passed to the
+    /// client's `jack_initialize` function; its format depends on the client (e.g. `"-i 2 -o 2"`
+    /// for `audioadapter`). The only other option which is meaningful here is `USE_EXACT_NAME`.
+    pub fn load_internal_client(
+        &self,
+        name: &str,
+        load_name: Option<&str>,
+        load_init: Option<&str>,
+        opts: options::Options)
+        -> Result<InternalClient, status::Status>
+    {
+        let cname = CString::new(name).unwrap();
+        let lname = load_name.map(|s| CString::new(s).unwrap());
+        let linit = load_init.map(|s| CString::new(s).unwrap());
+
+        let mut opts = opts;
+        opts.remove(options::LOAD_NAME | options::LOAD_INIT);
+        if lname.is_some() {
+            opts.insert(options::LOAD_NAME);
+        }
+
+        if linit.is_some() {
+            opts.insert(options::LOAD_INIT);
+        }
+
+        let mut status = 0 as jack_sys::jack_status_t;
+        let handle = unsafe {
+            ffi::internal_client_load(
+                self.c_client,
+                &cname,
+                opts.bits(),
+                &mut status,
+                lname.as_ref().map(|l| l.as_c_str()),
+                linit.as_ref().map(|i| i.as_c_str()))
+        };
+
+        check_handle(handle, status)?;
+
+        let name = self.internal_client_name(handle).unwrap_or_else(|| name.to_string());
+        Ok(InternalClient::new(handle, name))
+    }
+
+    /// Looks up an internal client which is already loaded by name
+    pub fn get_internal_client(&self, name: &str) -> Result<InternalClient, status::Status> {
+        let cname = CString::new(name).unwrap();
+        let mut status = 0 as jack_sys::jack_status_t;
+
+        let handle = unsafe {
+            ffi::jack_internal_client_handle(self.c_client, cname.as_ptr(), &mut status)
+        };
+
+        check_handle(handle, status)?;
+        Ok(InternalClient::new(handle, name.to_string()))
+    }
+
+    /// Returns the name of the internal client with the given handle, or None if there is no
+    /// such client
+    pub fn internal_client_name(&self, handle: jack_sys::jack_intclient_t) -> Option<String> {
+        unsafe {
+            let ptr = ffi::jack_get_internal_client_name(self.c_client, handle);
+            take_jack_string(ptr as *mut libc::c_char)
+        }
+    }
+
+    /// Unloads an internal client from the server
+    pub fn unload_internal_client(&self, client: InternalClient) -> Result<(), status::Status> {
+        let status = unsafe {
+            ffi::jack_internal_client_unload(self.c_client, client.handle())
+        };
+
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(status::Status::from_bits_truncate(status))
+        }
+    }
+
     /// Set the client's process callback handler.
     /// The client takes ownership of the handler, so be sure to set up any
     /// messaging queues before passing the handler off to the client
@@ -274,15 +817,32 @@ impl<'a> Client<'a> {
             args: *mut libc::c_void)
             -> libc::c_int
         {
-            let this = args as *mut T;
+            let this = &mut *(args as *mut ProcessState<T>);
             let ctx = CallbackContext::new();
-            (*this).process(&ctx, nframes)
+
+            if !this.stats.timing_enabled() {
+                return this.process(&ctx, nframes);
+            }
+
+            let start = Instant::now();
+            let ret = this.process(&ctx, nframes);
+            let srate = ffi::jack_get_sample_rate(this.client);
+            this.stats.record_cycle(start.elapsed(), nframes, srate);
+
+            ret
         }
 
         // create a box for this handler
         // this will allocate memory and move the object to the allocated memory
         // on the heap
-        let b = Box::new(handler);
+        let b = Box::new(ProcessState {
+            handler: handler,
+            client:  self.c_client,
+            stats:   self.stats.clone(),
+
+            #[cfg(feature = "alloc-detector")]
+            alloc_watch: self.alloc_watch.clone(),
+        });
 
         // get the pointer, this consumes the box, but does not move the
         // resulting memory anywhere
@@ -290,7 +850,7 @@ impl<'a> Client<'a> {
 
         let ret = unsafe {
             let ptr = ptr as *mut libc::c_void;
-            jack_sys::jack_set_process_callback(
+            ffi::jack_set_process_callback(
                 self.c_client, Some(process_callback::<T>), ptr)
         };
 
@@ -305,6 +865,70 @@ impl<'a> Client<'a> {
         }
     }
 
+    /// Runs `handler` on the client's process thread instead of calling a `ProcessHandler` once
+    /// per cycle. The handler is given a `CycleLoop`, which it uses to wait for each cycle; when
+    /// the handler returns, the thread exits. See `CycleLoop` for details.
+    ///
+    /// This can not be used together with `set_process_handler`.
+    pub fn set_process_thread<F: FnMut(&mut CycleLoop) + 'a>(&mut self, handler: F)
+        -> Result<(), status::Status>
+    {
+        unsafe extern "C" fn process_thread_callback(args: *mut libc::c_void) -> *mut libc::c_void {
+            let this = args as *mut ProcessThreadState;
+            let mut cycles = CycleLoop::new((*this).client);
+            ((*this).handler)(&mut cycles);
+            ptr::null_mut()
+        }
+
+        let b = Box::new(ProcessThreadState {
+            handler: Box::new(handler),
+            client:  self.c_client,
+        });
+
+        let ptr = Box::into_raw(b);
+
+        let ret = unsafe {
+            ffi::jack_set_process_thread(
+                self.c_client, Some(process_thread_callback), ptr as *mut libc::c_void)
+        };
+
+        let b = unsafe { Box::from_raw(ptr) };
+        if ret != 0 {
+            Err(status::FAILURE)
+        } else {
+            self.process_thread = Some(b);
+            Ok(())
+        }
+    }
+
+    /// Set the handler which is run on each thread JACK creates for this client, before the
+    /// thread runs any other callbacks. Must be called before `activate`.
+    pub fn set_thread_init_handler<T: ThreadInitHandler + 'a>(&mut self, handler: T)
+        -> Result<(), status::Status>
+    {
+        unsafe extern "C" fn thread_init_callback<T: ThreadInitHandler>(args: *mut libc::c_void) {
+            let this = args as *mut T;
+            (*this).thread_init()
+        }
+
+        let b = Box::new(handler);
+        let ptr = Box::into_raw(b);
+
+        let ret = unsafe {
+            ffi::jack_set_thread_init_callback(
+                self.c_client, Some(thread_init_callback::<T>), ptr as *mut libc::c_void)
+        };
+
+        // take ownership back either way; if jack refused the callback it will never be called
+        let b = unsafe { Box::from_raw(ptr) };
+        if ret != 0 {
+            Err(status::FAILURE)
+        } else {
+            self.thread_init_handler = Some(b);
+            Ok(())
+        }
+    }
+
     /// Set the client's metadata change handler.
     pub fn set_metadata_handler<T: MetadataHandler + 'a>(&mut self, handler: T)
         -> Result<(), status::Status>
@@ -313,7 +937,7 @@ impl<'a> Client<'a> {
             srate: NumFrames,
             args: *mut libc::c_void) -> i32
         {
-            let this = args as *mut T;
+            let this = args as *mut MetadataState<T>;
 
             (*this).sample_rate_changed(srate)
         }
@@ -324,7 +948,7 @@ impl<'a> Client<'a> {
             connect: libc::c_int,
             args: *mut libc::c_void)
         {
-            let this = args as *mut T;
+            let this = args as *mut MetadataState<T>;
             let status = if connect == 0 {
                 PortConnectStatus::PortsDisconnected
             } else {
@@ -337,12 +961,57 @@ impl<'a> Client<'a> {
         unsafe extern "C" fn xrun_callback<T: MetadataHandler>(
             args: *mut libc::c_void) -> i32
         {
-            let this = args as *mut T;
+            let this = args as *mut MetadataState<T>;
             (*this).on_xrun()
         }
 
-        let b = Box::new(handler);
+        unsafe extern "C" fn shutdown_callback<T: MetadataHandler>(args: *mut libc::c_void) {
+            let this = args as *mut MetadataState<T>;
+            (*this).on_shutdown()
+        }
+
+        unsafe extern "C" fn freewheel_callback<T: MetadataHandler>(
+            starting: libc::c_int,
+            args: *mut libc::c_void)
+        {
+            let this = args as *mut MetadataState<T>;
+            (*this).on_freewheel(starting != 0)
+        }
+
+        unsafe extern "C" fn latency_callback<T: MetadataHandler>(
+            mode: jack_sys::jack_latency_callback_mode_t,
+            args: *mut libc::c_void)
+        {
+            let this = args as *mut MetadataState<T>;
+            (*this).on_latency(LatencyMode::from_raw(mode))
+        }
+
+        unsafe extern "C" fn property_change_callback<T: MetadataHandler>(
+            subject: jack_sys::jack_uuid_t,
+            key: *const libc::c_char,
+            change: jack_sys::jack_property_change_t,
+            args: *mut libc::c_void)
+        {
+            let this = args as *mut MetadataState<T>;
+            let key = if key.is_null() {
+                None
+            } else {
+                CStr::from_ptr(key).to_str().ok()
+            };
+
+            (*this).on_property_change(subject, key, PropertyChange::from_raw(change))
+        }
+
+        let b = Box::new(MetadataState {
+            handler: handler,
+            stats:   self.stats.clone(),
+        });
+
         let cbs = b.callbacks_of_interest();
+        let wants_xrun = cbs.iter().any(|h| match *h {
+            MetadataHandlers::Xrun => true,
+            _                      => false,
+        });
 
         let ptr = Box::into_raw(b);
 
@@ -353,15 +1022,23 @@ impl<'a> Client<'a> {
             for h in cbs {
                 ret = match h {
                     MetadataHandlers::SampleRate =>
-                        jack_sys::jack_set_sample_rate_callback(
+                        ffi::jack_set_sample_rate_callback(
                             self.c_client, Some(srate_callback::<T>), ptr),
 
                     MetadataHandlers::PortConnect =>
-                        jack_sys::jack_set_port_connect_callback(
+                        ffi::jack_set_port_connect_callback(
                             self.c_client, Some(connect_callback::<T>), ptr),
 
-                    // MetadataHandlers::Shutdown
-                    // MetadataHandlers::Freewheel,
+                    MetadataHandlers::Freewheel =>
+                        ffi::jack_set_freewheel_callback(
+                            self.c_client, Some(freewheel_callback::<T>), ptr),
+
+                    MetadataHandlers::Shutdown => {
+                        // jack_on_shutdown can not fail
+                        ffi::jack_on_shutdown(self.c_client, Some(shutdown_callback::<T>), ptr);
+                        0
+                    },
+
                     // MetadataHandlers::BufferSize,
                     // MetadataHandlers::ClientRegistration,
                     // MetadataHandlers::PortRegistration,
@@ -369,9 +1046,17 @@ impl<'a> Client<'a> {
                     // MetadataHandlers::GraphOrder,
 
                      MetadataHandlers::Xrun =>
-                         jack_sys::jack_set_xrun_callback(
+                         ffi::jack_set_xrun_callback(
                              self.c_client, Some(xrun_callback::<T>), ptr),
 
+                    MetadataHandlers::Latency =>
+                        ffi::jack_set_latency_callback(
+                            self.c_client, Some(latency_callback::<T>), ptr),
+
+                    MetadataHandlers::PropertyChange =>
+                        ffi::jack_set_property_change_callback(
+                            self.c_client, Some(property_change_callback::<T>), ptr),
+
                     _  => unimplemented!(),
                 };
 
@@ -390,7 +1075,22 @@ impl<'a> Client<'a> {
             // create a box from the raw pointer. this does not allocate more memory
             let b = unsafe { Box::from_raw(ptr) };
             self.metadata_handler = Some(b);
+            self.xrun_callback_set = self.xrun_callback_set || wants_xrun;
+            Ok(())
+        }
+    }
+
+    /// Asks the server to recompute the total latencies of all ports in the graph.
+    ///
+    /// This should be called after a client changes the latency it reports for its ports outside
+    /// of a latency callback (for example, after a change to the length of an internal delay line).
+    pub fn recompute_total_latencies(&mut self) -> Result<(), status::Status> {
+        let ret = unsafe { ffi::jack_recompute_total_latencies(self.c_client) };
+
+        if ret == 0 {
             Ok(())
+        } else {
+            Err(status::FAILURE)
         }
     }
 
@@ -398,9 +1098,28 @@ impl<'a> Client<'a> {
     /// This will initiate
     /// callbacks into the `CallbackHandler` provided.
     pub fn activate(&self) -> Result<(), status::Status> {
+        // count xruns for the stats, unless the metadata handler's callback is already doing it
+        unsafe extern "C" fn stats_xrun_callback(args: *mut libc::c_void) -> i32 {
+            let stats = args as *const StatsCollector;
+            (*stats).record_client_xrun();
+            0
+        }
+
+        if !self.xrun_callback_set {
+            // the collector lives as long as the client does
+            let ptr = &*self.stats as *const StatsCollector as *mut libc::c_void;
+            let ret = unsafe {
+                ffi::jack_set_xrun_callback(self.c_client, Some(stats_xrun_callback), ptr)
+            };
+
+            if ret != 0 {
+                return Err(status::FAILURE);
+            }
+        }
+
         // TODO disable various other function calls after activate is called
         // do this via (self) -> ActivatedClient or something
-        let ret = unsafe { jack_sys::jack_activate(self.c_client) };
+        let ret = unsafe { ffi::jack_activate(self.c_client) };
 
         if ret != 0 {
             // TODO handle error
@@ -410,10 +1129,43 @@ impl<'a> Client<'a> {
         }
     }
 
+    /// Tells the JACK server to stop calling the client's callbacks and removes the client from
+    /// the processing graph. The client's ports are disconnected.
+    pub fn deactivate(&self) -> Result<(), status::Status> {
+        let ret = unsafe { ffi::jack_deactivate(self.c_client) };
+
+        if ret != 0 {
+            Err(status::FAILURE)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Starts (`true`) or stops freewheel mode. While freewheeling, the server stops waiting for
+    /// the audio hardware and runs the process callbacks of every client as fast as possible,
+    /// which is useful for rendering faster than realtime. See `render_offline`.
+    ///
+    /// Freewheel mode affects the whole server, not just this client.
+    pub fn set_freewheel(&self, on: bool) -> Result<(), status::Status> {
+        let ret = unsafe { ffi::jack_set_freewheel(self.c_client, on as libc::c_int) };
+
+        if ret != 0 {
+            Err(status::FAILURE)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Disconnects the client from the JACK server.
     /// This will also disconnect and destroy any of the ports which the client registered
+    ///
+    /// Clients which belong to the server (see `internal_client!`) can not be closed.
     pub fn close(&mut self) -> Result<(), &str> {
-        let ret = unsafe { jack_sys::jack_client_close(self.c_client) };
+        if !self.owned {
+            return Err("this client belongs to the server and can not be closed");
+        }
+
+        let ret = unsafe { ffi::jack_client_close(self.c_client) };
 
         if ret == 0 {
             Ok(())
@@ -422,12 +1174,63 @@ impl<'a> Client<'a> {
         }
     }
 
-    #[cfg(test)]
+    /// Wraps a process handler in a `Watchdog` which reports every cycle in which the handler
+    /// returned after more than `threshold` (a fraction, e.g. `0.75`) of the period had passed.
+    ///
+    /// `on_overrun` is called on the returned `WatchdogThread`, which must be kept alive for as
+    /// long as overruns should be reported. Pass the `Watchdog` to `set_process_handler`.
+    pub fn watchdog<H, F>(&self, handler: H, threshold: f32, on_overrun: F)
+        -> (Watchdog<H>, WatchdogThread)
+        where H: ProcessHandler,
+              F: FnMut(Overrun) + Send + 'static
+    {
+        Watchdog::new(handler, self.c_client, threshold, on_overrun)
+    }
+
+    /// Enables or disables timing of the process handler. Timing is disabled by default. See
+    /// `ClientStats`.
+    pub fn set_process_timing(&self, enabled: bool) {
+        self.stats.set_timing(enabled)
+    }
+
+    /// Returns the current statistics for this client
+    pub fn stats(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns a handle which can be used to read this client's statistics from another thread
+    pub fn stats_handle(&self) -> StatsHandle {
+        StatsHandle::new(self.stats.clone())
+    }
+
+    /// Returns the number of times the process callback has allocated or freed memory. See the
+    /// `allocdetect` module; this is always zero unless its `DetectingAllocator` is installed.
+    #[cfg(feature = "alloc-detector")]
+    pub fn alloc_violations(&self) -> usize {
+        self.alloc_watch.violations()
+    }
+
+    /// If set, the process callback panics at the end of any cycle in which it allocated or freed
+    /// memory. The panic cannot unwind into JACK, so this aborts the process; it is meant for
+    /// tests.
+    #[cfg(feature = "alloc-detector")]
+    pub fn set_panic_on_alloc(&self, panic: bool) {
+        self.alloc_watch.set_panic(panic)
+    }
+
+    pub(crate) fn raw(&self) -> *mut jack_sys::jack_client_t { self.c_client }
+
+    #[cfg(all(test, not(any(feature = "mock", feature = "dlopen"))))]
     pub unsafe fn get_raw(&self) -> *const jack_sys::jack_client_t { self.c_client }
 }
 
 // these tests are extremely fragile because they involve using a c library as the stub mechanism
-#[cfg(test)]
+//
+// The stub replaces libjack's symbols at link time, so it only sees calls made through jack_sys.
+// With the `mock` feature the calls go to the mock backend instead, and with `dlopen` they go to
+// the libjack loaded at runtime, so neither would reach the stub and every test would fail. The
+// same behaviour is tested against the mock in `mock::test` (`client_names`, `no_server`).
+#[cfg(all(test, not(any(feature = "mock", feature = "dlopen"))))]
 mod test {
     extern crate libc;
 
@@ -0,0 +1,337 @@
+//! This module contains helpers for moving audio between JACK's buffers, which always hold one
+//! channel of `DefaultAudioSample`s each, and interleaved buffers of other sample formats (the
+//! layout used by most audio files and many other audio APIs).
+//!
+//! None of these functions allocate, so they are safe to use in a process callback.
+//!
+//! Integer formats are scaled so that -1.0 to 1.0 maps to the full range of the integer, and
+//! values outside of the range are clipped. When reducing the word length (converting to an
+//! integer format), a `Dither` may be provided to add TPDF (triangular) dither before rounding.
+
+use callbackhandler::*;
+use port::*;
+use types::*;
+
+/// A sample format which can be converted to and from `DefaultAudioSample`
+pub trait Sample: Copy {
+    /// Converts this sample to a `DefaultAudioSample`
+    fn to_f32(self) -> DefaultAudioSample;
+
+    /// Converts a `DefaultAudioSample` to this format, clipping if the format has a limited range.
+    /// `noise` is added after scaling, in units of the least significant bit of the format.
+    /// Floating point formats ignore `noise`.
+    fn from_f32(x: DefaultAudioSample, noise: f32) -> Self;
+}
+
+/// A 24 bit integer sample, stored in the low 24 bits of an `i32` (sign extended).
+///
+/// This is the "24 in 32" layout, so a slice of `I24` is four bytes per sample. For packed 24 bit
+/// data (three bytes per sample, as in most WAV files) convert each sample with `to_le_bytes` and
+/// `from_le_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct I24(pub i32);
+
+impl I24 {
+    pub const MAX: i32 = (1 << 23) - 1;
+    pub const MIN: i32 = -(1 << 23);
+
+    /// Packs the sample into three little endian bytes
+    pub fn to_le_bytes(self) -> [u8; 3] {
+        let b = self.0.to_le_bytes();
+        [b[0], b[1], b[2]]
+    }
+
+    /// Unpacks a sample from three little endian bytes, sign extending it
+    pub fn from_le_bytes(b: [u8; 3]) -> Self {
+        // shift the top byte into place and back down again to copy the sign bit
+        I24(i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8)
+    }
+}
+
+// does the scaling, rounding, and clipping for all of the integer formats
+// f64 is used so that 32 bit integers don't lose precision
+fn float_to_int(x: DefaultAudioSample, noise: f32, min: i64, max: i64) -> i64 {
+    let scaled = (x as f64) * (-(min as f64)) + (noise as f64);
+    let r = scaled.round();
+
+    if r >= max as f64 {
+        max
+    } else if r <= min as f64 {
+        min
+    } else {
+        r as i64
+    }
+}
+
+impl Sample for i16 {
+    fn to_f32(self) -> DefaultAudioSample {
+        self as DefaultAudioSample / 32768.0
+    }
+
+    fn from_f32(x: DefaultAudioSample, noise: f32) -> Self {
+        float_to_int(x, noise, i16::min_value() as i64, i16::max_value() as i64) as i16
+    }
+}
+
+impl Sample for I24 {
+    fn to_f32(self) -> DefaultAudioSample {
+        self.0 as DefaultAudioSample / 8388608.0
+    }
+
+    fn from_f32(x: DefaultAudioSample, noise: f32) -> Self {
+        I24(float_to_int(x, noise, I24::MIN as i64, I24::MAX as i64) as i32)
+    }
+}
+
+impl Sample for i32 {
+    fn to_f32(self) -> DefaultAudioSample {
+        (self as f64 / 2147483648.0) as DefaultAudioSample
+    }
+
+    fn from_f32(x: DefaultAudioSample, noise: f32) -> Self {
+        float_to_int(x, noise, i32::min_value() as i64, i32::max_value() as i64) as i32
+    }
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> DefaultAudioSample { self }
+    fn from_f32(x: DefaultAudioSample, _noise: f32) -> Self { x }
+}
+
+impl Sample for f64 {
+    fn to_f32(self) -> DefaultAudioSample { self as DefaultAudioSample }
+    fn from_f32(x: DefaultAudioSample, _noise: f32) -> Self { x as f64 }
+}
+
+/// A source of TPDF (triangular probability density function) dither noise.
+///
+/// This uses a small xorshift generator, so it is cheap and does not allocate. It is not suitable
+/// for anything other than dither.
+#[derive(Debug, Clone)]
+pub struct Dither {
+    state: u32,
+}
+
+impl Dither {
+    /// Creates a new dither source with the given seed. A seed of zero is replaced with a fixed
+    /// non-zero value (xorshift cannot escape from zero).
+    pub fn new(seed: u32) -> Self {
+        Dither { state: if seed == 0 { 0x9e3779b9 } else { seed } }
+    }
+
+    fn next_uniform(&mut self) -> f32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+
+        // uniform in [0, 1)
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Returns the next noise value, in the range (-1, 1), in units of the least significant bit
+    pub fn next(&mut self) -> f32 {
+        self.next_uniform() - self.next_uniform()
+    }
+}
+
+impl Default for Dither {
+    fn default() -> Self { Dither::new(0) }
+}
+
+/// Interleaves the given channels into `out`, converting to the sample format of `out`.
+///
+/// The number of frames converted is the smallest of the channel lengths and the number of
+/// complete frames which fit in `out`. Returns the number of frames written.
+pub fn interleave<S: Sample>(
+    channels: &[&[DefaultAudioSample]],
+    out: &mut [S],
+    mut dither: Option<&mut Dither>)
+    -> usize
+{
+    let n = channels.len();
+    if n == 0 {
+        return 0;
+    }
+
+    let frames = channels.iter().map(|c| c.len()).fold(out.len() / n, ::std::cmp::min);
+    for (ch, input) in channels.iter().enumerate() {
+        for i in 0..frames {
+            let noise = dither.as_mut().map_or(0.0, |d| d.next());
+            out[i * n + ch] = S::from_f32(input[i], noise);
+        }
+    }
+
+    frames
+}
+
+/// Deinterleaves `input` into the given channels, converting from the sample format of `input`.
+///
+/// The number of frames converted is the smallest of the channel lengths and the number of
+/// complete frames in `input`. Returns the number of frames written.
+pub fn deinterleave<S: Sample>(input: &[S], channels: &mut [&mut [DefaultAudioSample]]) -> usize {
+    let n = channels.len();
+    if n == 0 {
+        return 0;
+    }
+
+    let frames = channels.iter().map(|c| c.len()).fold(input.len() / n, ::std::cmp::min);
+    for (ch, output) in channels.iter_mut().enumerate() {
+        for i in 0..frames {
+            output[i] = input[i * n + ch].to_f32();
+        }
+    }
+
+    frames
+}
+
+/// Interleaves the buffers of the given input ports into `out`, converting to the sample format
+/// of `out`. See `interleave`.
+pub fn interleave_ports<S: Sample>(
+    ports: &[InputPortHandle<DefaultAudioSample>],
+    nframes: NumFrames,
+    ctx: &CallbackContext,
+    out: &mut [S],
+    mut dither: Option<&mut Dither>)
+    -> usize
+{
+    let n = ports.len();
+    if n == 0 {
+        return 0;
+    }
+
+    let frames = ::std::cmp::min(nframes as usize, out.len() / n);
+    for (ch, port) in ports.iter().enumerate() {
+        let input = port.get_read_buffer(nframes, ctx);
+        for i in 0..frames {
+            let noise = dither.as_mut().map_or(0.0, |d| d.next());
+            out[i * n + ch] = S::from_f32(input[i], noise);
+        }
+    }
+
+    frames
+}
+
+/// Deinterleaves `input` into the buffers of the given output ports, converting from the sample
+/// format of `input`. See `deinterleave`.
+///
+/// If `input` does not contain `nframes` complete frames, the remainder of each port's buffer is
+/// filled with silence.
+pub fn deinterleave_ports<S: Sample>(
+    input: &[S],
+    ports: &[OutputPortHandle<DefaultAudioSample>],
+    nframes: NumFrames,
+    ctx: &CallbackContext)
+    -> usize
+{
+    let n = ports.len();
+    if n == 0 {
+        return 0;
+    }
+
+    let frames = ::std::cmp::min(nframes as usize, input.len() / n);
+    for (ch, port) in ports.iter().enumerate() {
+        let output = port.get_write_buffer(nframes, ctx);
+        for i in 0..frames {
+            output[i] = input[i * n + ch].to_f32();
+        }
+
+        for o in output[frames..].iter_mut() {
+            *o = 0.0;
+        }
+    }
+
+    frames
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn int_scaling_and_clipping() {
+        assert_eq!(i16::from_f32(0.0, 0.0), 0);
+        assert_eq!(i16::from_f32(0.5, 0.0), 16384);
+        assert_eq!(i16::from_f32(-1.0, 0.0), i16::min_value());
+        assert_eq!(i16::from_f32(1.0, 0.0), i16::max_value());
+        assert_eq!(i16::from_f32(4.0, 0.0), i16::max_value());
+        assert_eq!(i16::from_f32(-4.0, 0.0), i16::min_value());
+
+        assert_eq!(I24::from_f32(1.5, 0.0), I24(I24::MAX));
+        assert_eq!(I24::from_f32(-0.5, 0.0), I24(-(1 << 22)));
+
+        assert_eq!(i32::from_f32(1.0, 0.0), i32::max_value());
+        assert_eq!(i32::from_f32(-1.0, 0.0), i32::min_value());
+    }
+
+    #[test]
+    fn round_trip() {
+        for &x in &[0.0, 0.25, -0.25, 0.75, -1.0] {
+            assert_eq!(i16::from_f32(x, 0.0).to_f32(), x);
+            assert_eq!(I24::from_f32(x, 0.0).to_f32(), x);
+            assert_eq!(i32::from_f32(x, 0.0).to_f32(), x);
+            assert_eq!(f64::from_f32(x, 0.0).to_f32(), x);
+        }
+    }
+
+    #[test]
+    fn i24_packing() {
+        assert_eq!(I24(0x123456).to_le_bytes(), [0x56, 0x34, 0x12]);
+        assert_eq!(I24(-1).to_le_bytes(), [0xff, 0xff, 0xff]);
+        assert_eq!(I24::from_le_bytes([0x56, 0x34, 0x12]), I24(0x123456));
+        assert_eq!(I24::from_le_bytes([0x00, 0x00, 0x80]), I24(I24::MIN));
+
+        for &x in &[0, 1, -1, I24::MAX, I24::MIN] {
+            assert_eq!(I24::from_le_bytes(I24(x).to_le_bytes()), I24(x));
+        }
+    }
+
+    #[test]
+    fn dither_is_bounded() {
+        let mut d = Dither::new(1234);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let n = d.next();
+            assert!(n > -1.0 && n < 1.0);
+            sum += n;
+        }
+
+        // triangular noise is centered on zero
+        assert!((sum / 10000.0 as f32).abs() < 0.05);
+
+        // dither never moves a sample by more than one lsb
+        let mut d = Dither::new(99);
+        for _ in 0..1000 {
+            let s = i16::from_f32(0.5, d.next());
+            assert!(s >= 16383 && s <= 16385);
+        }
+    }
+
+    #[test]
+    fn interleave_and_deinterleave() {
+        let left  = [0.5, -0.5, 0.25];
+        let right = [-1.0, 1.0, 0.0];
+
+        let mut out = [0i16; 6];
+        let n = interleave(&[&left, &right], &mut out, None);
+        assert_eq!(n, 3);
+        assert_eq!(out, [16384, -32768, -16384, 32767, 8192, 0]);
+
+        let mut l = [0.0; 3];
+        let mut r = [0.0; 3];
+        let n = deinterleave(&out, &mut [&mut l, &mut r]);
+        assert_eq!(n, 3);
+        assert_eq!(l, left);
+        assert_eq!(r, [-1.0, 32767.0 / 32768.0, 0.0]);
+    }
+
+    #[test]
+    fn interleave_short_output() {
+        let a = [1.0; 8];
+        let b = [1.0; 8];
+        let mut out = [0.0f64; 5];
+        assert_eq!(interleave(&[&a, &b], &mut out, None), 2);
+        assert_eq!(out, [1.0, 1.0, 1.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,95 @@
+use ffi;
+use jack_sys;
+
+use std::ops::Deref;
+
+use callbackhandler::*;
+use types::*;
+
+/// Drives a process thread set with `Client::set_process_thread`.
+///
+/// Instead of being called back once per cycle, the thread calls `wait` to block until the next
+/// cycle starts. The returned `Cycle` can be used exactly like the `CallbackContext` given to a
+/// `ProcessHandler`: port buffers fetched with it are only valid until the cycle is signalled,
+/// which happens when it is dropped (or when `Cycle::signal` is called).
+///
+/// ```no_run
+/// use easyjack::{Client, CycleLoop, DefaultAudioSample, InputPortHandle};
+///
+/// # fn f(client: &mut Client, port: InputPortHandle<DefaultAudioSample>) {
+/// client.set_process_thread(move |cycles: &mut CycleLoop| {
+///     loop {
+///         let cycle = cycles.wait();
+///         let input = port.get_read_buffer(cycle.nframes(), &cycle);
+///         // ...
+///     }
+/// }).unwrap();
+/// # }
+/// ```
+///
+/// If the server shuts down while the thread is waiting, JACK may exit the thread from inside
+/// `wait`, without returning.
+pub struct CycleLoop {
+    client: *mut jack_sys::jack_client_t,
+}
+
+impl CycleLoop {
+    #[doc(hidden)]
+    pub fn new(client: *mut jack_sys::jack_client_t) -> Self {
+        CycleLoop { client: client }
+    }
+
+    /// Blocks until the next cycle starts, then returns it
+    pub fn wait(&mut self) -> Cycle<'_> {
+        let nframes = unsafe { ffi::jack_cycle_wait(self.client) };
+        Cycle {
+            owner:   self,
+            ctx:     CallbackContext::new(),
+            nframes: nframes,
+            status:  0,
+        }
+    }
+}
+
+/// One cycle of a process thread. See `CycleLoop`.
+///
+/// This dereferences to the `CallbackContext` for the cycle, so it can be passed anywhere a
+/// context is needed.
+pub struct Cycle<'a> {
+    owner:   &'a mut CycleLoop,
+    ctx:     CallbackContext,
+    nframes: NumFrames,
+    status:  i32,
+}
+
+impl<'a> Cycle<'a> {
+    /// Returns the number of frames to process in this cycle
+    pub fn nframes(&self) -> NumFrames {
+        self.nframes
+    }
+
+    /// Returns the context for this cycle
+    pub fn context(&self) -> &CallbackContext {
+        &self.ctx
+    }
+
+    /// Completes the cycle, reporting `status` to JACK (non-zero means the client failed and
+    /// should be removed from the graph). Dropping the cycle completes it with a status of zero.
+    pub fn signal(mut self, status: i32) {
+        self.status = status;
+    }
+}
+
+impl<'a> Deref for Cycle<'a> {
+    type Target = CallbackContext;
+
+    fn deref(&self) -> &CallbackContext {
+        &self.ctx
+    }
+}
+
+impl<'a> Drop for Cycle<'a> {
+    fn drop(&mut self) {
+        unsafe { ffi::jack_cycle_signal(self.owner.client, self.status) };
+    }
+}
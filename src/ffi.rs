@@ -0,0 +1,161 @@
+//! The functions from libjack which this crate calls. All of the crate's calls into libjack go
+//! through here, except for the `server` module's (which talks to libjackserver's control api, and
+//! has no mock or runtime loaded version). That lets the `mock` feature swap in the in-process
+//! implementation from `mockbackend`, and the `dlopen` feature swap in the functions `weakjack`
+//! loads at runtime. Types and constants still come from jack_sys.
+//!
+//! If both features are enabled, `mock` wins.
+//!
+//! The mock can not define C variadic functions, so `jack_client_open` and
+//! `jack_internal_client_load` are wrapped with fixed argument lists.
+
+use jack_sys;
+
+use std::ffi::CStr;
+
+#[cfg(not(any(feature = "mock", feature = "dlopen")))]
+use jack_sys as imp;
+
+#[cfg(all(feature = "dlopen", not(feature = "mock")))]
+use weakjack as imp;
+
+#[cfg(feature = "mock")]
+use mockbackend as imp;
+
+pub(crate) use self::imp::{
+    jack_acquire_real_time_scheduling,
+    jack_activate,
+    jack_client_close,
+    jack_client_create_thread,
+    jack_client_get_uuid,
+    jack_client_max_real_time_priority,
+    jack_client_real_time_priority,
+    jack_connect,
+    jack_cycle_signal,
+    jack_cycle_wait,
+    jack_deactivate,
+    jack_disconnect,
+    jack_drop_real_time_scheduling,
+    jack_frames_since_cycle_start,
+    jack_free,
+    jack_free_description,
+    jack_get_all_properties,
+    jack_get_buffer_size,
+    jack_get_client_name,
+    jack_get_client_name_by_uuid,
+    jack_get_internal_client_name,
+    jack_get_ports,
+    jack_get_properties,
+    jack_get_property,
+    jack_get_sample_rate,
+    jack_get_uuid_for_client_name,
+    jack_get_xrun_delayed_usecs,
+    jack_internal_client_handle,
+    jack_internal_client_unload,
+    jack_is_realtime,
+    jack_last_frame_time,
+    jack_midi_clear_buffer,
+    jack_midi_event_get,
+    jack_midi_event_write,
+    jack_midi_get_event_count,
+    jack_midi_get_lost_event_count,
+    jack_midi_max_event_size,
+    jack_on_shutdown,
+    jack_port_by_id,
+    jack_port_by_name,
+    jack_port_flags,
+    jack_port_get_aliases,
+    jack_port_get_buffer,
+    jack_port_get_latency_range,
+    jack_port_name,
+    jack_port_name_size,
+    jack_port_register,
+    jack_port_set_alias,
+    jack_port_set_latency_range,
+    jack_port_type_get_buffer_size,
+    jack_port_unregister,
+    jack_port_unset_alias,
+    jack_port_uuid,
+    jack_recompute_total_latencies,
+    jack_remove_all_properties,
+    jack_remove_properties,
+    jack_remove_property,
+    jack_set_error_function,
+    jack_set_freewheel,
+    jack_set_freewheel_callback,
+    jack_set_info_function,
+    jack_set_latency_callback,
+    jack_set_port_connect_callback,
+    jack_set_process_callback,
+    jack_set_process_thread,
+    jack_set_property,
+    jack_set_property_change_callback,
+    jack_set_sample_rate_callback,
+    jack_set_thread_init_callback,
+    jack_set_xrun_callback,
+};
+
+/// `jack_client_open`, passing `server` as the server name if one is given. `opts` must contain
+/// `JackServerName` exactly when `server` is given.
+#[cfg(not(any(feature = "mock", feature = "dlopen")))]
+pub(crate) unsafe fn client_open(
+    name: &CStr,
+    opts: jack_sys::jack_options_t,
+    status: *mut jack_sys::jack_status_t,
+    server: Option<&CStr>)
+    -> *mut jack_sys::jack_client_t
+{
+    match server {
+        Some(s) => jack_sys::jack_client_open(name.as_ptr(), opts, status, s.as_ptr()),
+        None    => jack_sys::jack_client_open(name.as_ptr(), opts, status),
+    }
+}
+
+#[cfg(any(feature = "mock", feature = "dlopen"))]
+pub(crate) unsafe fn client_open(
+    name: &CStr,
+    opts: jack_sys::jack_options_t,
+    status: *mut jack_sys::jack_status_t,
+    server: Option<&CStr>)
+    -> *mut jack_sys::jack_client_t
+{
+    imp::client_open(name, opts, status, server)
+}
+
+/// `jack_internal_client_load`. `opts` must contain `JackLoadName` exactly when `load_name` is
+/// given, and `JackLoadInit` exactly when `load_init` is given.
+#[cfg(not(any(feature = "mock", feature = "dlopen")))]
+pub(crate) unsafe fn internal_client_load(
+    client: *mut jack_sys::jack_client_t,
+    name: &CStr,
+    opts: jack_sys::jack_options_t,
+    status: *mut jack_sys::jack_status_t,
+    load_name: Option<&CStr>,
+    load_init: Option<&CStr>)
+    -> jack_sys::jack_intclient_t
+{
+    let c = client;
+    let n = name.as_ptr();
+
+    // the extra arguments are read by jack in this order, one for each flag which is set
+    match (load_name, load_init) {
+        (Some(l), Some(i)) =>
+            jack_sys::jack_internal_client_load(c, n, opts, status, l.as_ptr(), i.as_ptr()),
+        (Some(l), None)    => jack_sys::jack_internal_client_load(c, n, opts, status, l.as_ptr()),
+        (None, Some(i))    => jack_sys::jack_internal_client_load(c, n, opts, status, i.as_ptr()),
+        (None, None)       => jack_sys::jack_internal_client_load(c, n, opts, status),
+    }
+}
+
+#[cfg(any(feature = "mock", feature = "dlopen"))]
+pub(crate) unsafe fn internal_client_load(
+    client: *mut jack_sys::jack_client_t,
+    name: &CStr,
+    opts: jack_sys::jack_options_t,
+    status: *mut jack_sys::jack_status_t,
+    load_name: Option<&CStr>,
+    load_init: Option<&CStr>)
+    -> jack_sys::jack_intclient_t
+{
+    imp::internal_client_load(client, name, opts, status, load_name, load_init)
+}
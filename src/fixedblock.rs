@@ -0,0 +1,314 @@
+use std::cmp;
+use std::slice;
+
+use callbackhandler::*;
+use port::*;
+use types::*;
+
+/// This trait defines a handler which processes audio in blocks of a fixed size.
+/// See `FixedBlockProcessor`.
+pub trait BlockProcessHandler {
+    /// Process exactly one block. Each slice in `inputs` and `outputs` holds exactly `block_size`
+    /// frames, where `block_size` is the size given to `FixedBlockProcessor::new`. There is one
+    /// slice per port, in the same order as the ports given to `FixedBlockProcessor::new`.
+    fn process_block(
+        &mut self,
+        inputs: &[&[DefaultAudioSample]],
+        outputs: &mut [&mut [DefaultAudioSample]]);
+}
+
+/// Adapts a `BlockProcessHandler`, which always wants blocks of a fixed size, to the (possibly
+/// different, possibly changing) period size JACK calls the process callback with.
+///
+/// Input is buffered across JACK cycles until a full block is available, then the inner handler
+/// is run and its output is played back over the following cycles. This adds exactly
+/// `block_size` frames of latency, regardless of the JACK period size, so nothing needs to be
+/// reconfigured when the server's buffer size changes.
+///
+/// The added latency should be reported to JACK so that other clients can compensate for it. Use
+/// `latency_reporter` to get an object which can do this from `MetadataHandler::on_latency`.
+///
+/// All of the buffers are allocated when the processor is created, so processing never
+/// allocates.
+pub struct FixedBlockProcessor<H> {
+    inner:      H,
+    block_size: usize,
+    inputs:     Vec<InputPortHandle<DefaultAudioSample>>,
+    outputs:    Vec<OutputPortHandle<DefaultAudioSample>>,
+
+    // one block per channel. `pos` is the position in the current block, shared by the inputs and
+    // the outputs: the input being collected and the output of the previous block are consumed
+    // in lock step
+    in_blocks:  Vec<Vec<DefaultAudioSample>>,
+    out_blocks: Vec<Vec<DefaultAudioSample>>,
+    pos:        usize,
+
+    // preallocated storage for the slices handed to the inner handler and for the port buffers.
+    // These are only valid for the duration of a single call.
+    in_views:  Vec<*const [DefaultAudioSample]>,
+    out_views: Vec<*mut [DefaultAudioSample]>,
+    port_in:   Vec<*const [DefaultAudioSample]>,
+    port_out:  Vec<*mut [DefaultAudioSample]>,
+}
+
+impl<H: BlockProcessHandler> FixedBlockProcessor<H> {
+    /// Creates a new processor which will run `inner` on blocks of exactly `block_size` frames.
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn new(
+        inner: H,
+        block_size: usize,
+        inputs: Vec<InputPortHandle<DefaultAudioSample>>,
+        outputs: Vec<OutputPortHandle<DefaultAudioSample>>)
+        -> Self
+    {
+        let mut p = Self::with_channels(inner, block_size, inputs.len(), outputs.len());
+        p.inputs = inputs;
+        p.outputs = outputs;
+        p
+    }
+
+    /// Creates a new processor which is not tied to any ports. It can only be driven with
+    /// `process_buffers`, which must be given `inputs` input and `outputs` output buffers.
+    ///
+    /// Panics if `block_size` is zero.
+    pub fn with_channels(inner: H, block_size: usize, inputs: usize, outputs: usize) -> Self {
+        assert!(block_size > 0, "block size must not be zero");
+
+        FixedBlockProcessor {
+            inner:      inner,
+            block_size: block_size,
+            inputs:     Vec::new(),
+            outputs:    Vec::new(),
+            in_blocks:  vec![vec![0.0; block_size]; inputs],
+            out_blocks: vec![vec![0.0; block_size]; outputs],
+            pos:        0,
+            in_views:   Vec::with_capacity(inputs),
+            out_views:  Vec::with_capacity(outputs),
+            port_in:    Vec::with_capacity(inputs),
+            port_out:   Vec::with_capacity(outputs),
+        }
+    }
+
+    /// Returns the latency (in frames) added by the processor
+    pub fn latency(&self) -> NumFrames {
+        self.block_size as NumFrames
+    }
+
+    /// Returns an object which can report the latency added by this processor to JACK.
+    pub fn latency_reporter(&self) -> LatencyReporter {
+        LatencyReporter {
+            inputs:  self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            added:   self.latency(),
+        }
+    }
+
+    /// Returns a reference to the inner handler
+    pub fn inner(&self) -> &H { &self.inner }
+
+    /// Returns a mutable reference to the inner handler
+    pub fn inner_mut(&mut self) -> &mut H { &mut self.inner }
+
+    /// Runs the processor on buffers which have already been fetched (for example with
+    /// `AudioBus::read_buffers` and `AudioBus::write_buffers`) instead of on the ports given to
+    /// `new`.
+    ///
+    /// There must be one input buffer per input channel and one output buffer per output channel,
+    /// and all of the buffers must be the same length.
+    pub fn process_buffers(
+        &mut self,
+        inputs: &[&[DefaultAudioSample]],
+        outputs: &mut [&mut [DefaultAudioSample]])
+    {
+        assert_eq!(inputs.len(), self.in_blocks.len());
+        assert_eq!(outputs.len(), self.out_blocks.len());
+
+        let nframes = inputs.iter().map(|b| b.len())
+            .chain(outputs.iter().map(|b| b.len()))
+            .next()
+            .unwrap_or(0);
+
+        let mut done = 0;
+        while done < nframes {
+            let len = cmp::min(nframes - done, self.block_size - self.pos);
+            let (src, dst) = (done..done + len, self.pos..self.pos + len);
+
+            for (block, input) in self.in_blocks.iter_mut().zip(inputs) {
+                block[dst.clone()].copy_from_slice(&input[src.clone()]);
+            }
+
+            for (block, output) in self.out_blocks.iter().zip(outputs.iter_mut()) {
+                output[src.clone()].copy_from_slice(&block[dst.clone()]);
+            }
+
+            done += len;
+            self.pos += len;
+
+            if self.pos == self.block_size {
+                self.run_inner();
+                self.pos = 0;
+            }
+        }
+    }
+
+    fn run_inner(&mut self) {
+        self.in_views.clear();
+        self.out_views.clear();
+
+        for b in &self.in_blocks {
+            self.in_views.push(&b[..] as *const [DefaultAudioSample]);
+        }
+
+        for b in &mut self.out_blocks {
+            self.out_views.push(&mut b[..] as *mut [DefaultAudioSample]);
+        }
+
+        // raw slice pointers have the same layout as slice references, and the blocks are all
+        // distinct allocations, so handing out these references is okay
+        unsafe {
+            let inputs = slice::from_raw_parts(
+                self.in_views.as_ptr() as *const &[DefaultAudioSample],
+                self.in_views.len());
+
+            let outputs = slice::from_raw_parts_mut(
+                self.out_views.as_mut_ptr() as *mut &mut [DefaultAudioSample],
+                self.out_views.len());
+
+            self.inner.process_block(inputs, outputs);
+        }
+    }
+}
+
+impl<H: BlockProcessHandler> ProcessHandler for FixedBlockProcessor<H> {
+    fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+        self.port_in.clear();
+        self.port_out.clear();
+
+        for p in &self.inputs {
+            self.port_in.push(p.get_read_buffer(nframes, ctx) as *const [DefaultAudioSample]);
+        }
+
+        for p in &self.outputs {
+            self.port_out.push(p.get_write_buffer(nframes, ctx) as *mut [DefaultAudioSample]);
+        }
+
+        // these point at jack's buffers, which live until the end of the cycle. The storage for
+        // the pointers is not touched by process_buffers.
+        unsafe {
+            let inputs = slice::from_raw_parts(
+                self.port_in.as_ptr() as *const &[DefaultAudioSample],
+                self.port_in.len());
+
+            let outputs = slice::from_raw_parts_mut(
+                self.port_out.as_mut_ptr() as *mut &mut [DefaultAudioSample],
+                self.port_out.len());
+
+            self.process_buffers(inputs, outputs);
+        }
+
+        0
+    }
+}
+
+/// Reports the latency added by a `FixedBlockProcessor` to JACK.
+///
+/// Call `report` from `MetadataHandler::on_latency` (and make sure the handler asks for the
+/// `MetadataHandlers::Latency` callback).
+#[derive(Debug, Clone)]
+pub struct LatencyReporter {
+    inputs:  Vec<InputPortHandle<DefaultAudioSample>>,
+    outputs: Vec<OutputPortHandle<DefaultAudioSample>>,
+    added:   NumFrames,
+}
+
+impl LatencyReporter {
+    /// Propagates latency across the processor for the given mode.
+    ///
+    /// For capture latency, the largest range of the inputs plus the added latency is set on each
+    /// output. For playback latency, the largest range of the outputs plus the added latency is
+    /// set on each input.
+    pub fn report(&self, mode: LatencyMode) {
+        match mode {
+            LatencyMode::Capture => {
+                let range = self.added_to(self.inputs.iter().map(|p| p.get_latency_range(mode)));
+                for p in &self.outputs {
+                    p.set_latency_range(mode, range);
+                }
+            },
+
+            LatencyMode::Playback => {
+                let range = self.added_to(self.outputs.iter().map(|p| p.get_latency_range(mode)));
+                for p in &self.inputs {
+                    p.set_latency_range(mode, range);
+                }
+            },
+        }
+    }
+
+    fn added_to<I: Iterator<Item=LatencyRange>>(&self, ranges: I) -> LatencyRange {
+        let mut range: Option<LatencyRange> = None;
+        for r in ranges {
+            range = Some(match range {
+                None    => r,
+                Some(m) => LatencyRange {
+                    min: cmp::min(m.min, r.min),
+                    max: cmp::max(m.max, r.max),
+                },
+            });
+        }
+
+        let range = range.unwrap_or_default();
+        LatencyRange { min: range.min + self.added, max: range.max + self.added }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // doubles the input and counts the blocks it sees
+    struct Doubler {
+        blocks: usize,
+    }
+
+    impl BlockProcessHandler for Doubler {
+        fn process_block(
+            &mut self,
+            inputs: &[&[DefaultAudioSample]],
+            outputs: &mut [&mut [DefaultAudioSample]])
+        {
+            assert_eq!(inputs[0].len(), 4);
+            assert_eq!(outputs[0].len(), 4);
+
+            for (o, i) in outputs[0].iter_mut().zip(inputs[0]) {
+                *o = i * 2.0;
+            }
+
+            self.blocks += 1;
+        }
+    }
+
+    #[test]
+    fn reblocks_with_constant_latency() {
+        let mut p = FixedBlockProcessor::with_channels(Doubler { blocks: 0 }, 4, 1, 1);
+        assert_eq!(p.latency(), 4);
+
+        // feed periods of varying size, none of them multiples of the block size
+        let input: Vec<DefaultAudioSample> = (1..21).map(|x| x as DefaultAudioSample).collect();
+        let mut output = vec![0.0; input.len()];
+
+        let mut start = 0;
+        for &period in &[3, 5, 1, 7, 4] {
+            let end = start + period;
+            p.process_buffers(&[&input[start..end]], &mut [&mut output[start..end]]);
+            start = end;
+        }
+
+        assert_eq!(p.inner().blocks, 5);
+        for i in 0..output.len() {
+            let expected = if i < 4 { 0.0 } else { input[i - 4] * 2.0 };
+            assert_eq!(output[i], expected);
+        }
+    }
+}
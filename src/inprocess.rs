@@ -0,0 +1,220 @@
+//! Support for writing internal clients: clients which are built as shared objects and run
+//! inside the JACK server process. See `internal_client!`.
+
+use getopts;
+use jack_sys;
+use libc;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+
+use client::*;
+use metadata::*;
+use types::*;
+
+/// Exports `jack_initialize` and `jack_finish` from a `cdylib`, so that it can be loaded into
+/// the server as an internal client (with `jack_load`, or `Client::load_internal_client`).
+///
+/// The argument is a function (or closure) taking a `&mut Client<'static>` and the `&InitArgs`
+/// the client was loaded with, and returning `Result<(), status::Status>`. It should register
+/// ports and set handlers exactly like a normal client would; easyjack activates the client after
+/// it returns `Ok`. If it returns `Err` or panics, loading fails.
+///
+/// ```ignore
+/// #[macro_use]
+/// extern crate easyjack;
+///
+/// use easyjack::*;
+///
+/// fn init(client: &mut Client<'static>, args: &InitArgs) -> Result<(), status::Status> {
+///     let gain = args.args().get(0).and_then(|g| g.parse().ok()).unwrap_or(1.0);
+///     let input = client.register_input_audio_port("in")?;
+///     let output = client.register_output_audio_port("out")?;
+///     client.set_process_handler(Gain { gain, input, output })
+/// }
+///
+/// internal_client!(init);
+/// ```
+///
+/// The client belongs to the server, so it can not be closed. It is dropped (along with its
+/// handlers) when the server calls `jack_finish`. Only one instance of a shared object can be
+/// loaded into a server at a time.
+#[macro_export]
+macro_rules! internal_client {
+    ($init:expr) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn jack_initialize(
+            client: *mut ::std::os::raw::c_void,
+            load_init: *const ::std::os::raw::c_char)
+            -> ::std::os::raw::c_int
+        {
+            $crate::initialize_internal_client(client, load_init, $init)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn jack_finish(_arg: *mut ::std::os::raw::c_void) {
+            $crate::finish_internal_client()
+        }
+    }
+}
+
+/// The initialization string an internal client was loaded with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitArgs {
+    raw:  String,
+    args: Vec<String>,
+}
+
+impl InitArgs {
+    /// Splits an initialization string into arguments. Arguments are separated by whitespace and
+    /// may be quoted with single or double quotes; a backslash escapes the next character.
+    pub fn new(raw: &str) -> Self {
+        InitArgs {
+            raw:  raw.to_string(),
+            args: split_args(raw),
+        }
+    }
+
+    /// Returns the initialization string exactly as it was given
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Returns the arguments in the initialization string
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Parses the arguments with `getopts`
+    pub fn parse(&self, opts: &getopts::Options) -> getopts::Result {
+        opts.parse(&self.args)
+    }
+}
+
+fn split_args(s: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', q) if q != Some('\'') => {
+                if let Some(n) = chars.next() {
+                    cur.push(n);
+                }
+
+                in_arg = true;
+            },
+
+            (c, Some(q)) if c == q => quote = None,
+            (c, Some(_)) => cur.push(c),
+
+            ('"', None) | ('\'', None) => {
+                quote = Some(c);
+                in_arg = true;
+            },
+
+            (c, None) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(cur.clone());
+                    cur.clear();
+                    in_arg = false;
+                }
+            },
+
+            (c, None) => {
+                cur.push(c);
+                in_arg = true;
+            },
+        }
+    }
+
+    if in_arg {
+        args.push(cur);
+    }
+
+    args
+}
+
+// the client for the loaded instance, kept alive (but otherwise untouched) until jack_finish
+#[allow(dead_code)]
+struct Instance(Box<Client<'static>>);
+
+// the client is only touched by the server's calls to jack_initialize and jack_finish
+unsafe impl Send for Instance {}
+
+static INSTANCE: Mutex<Option<Instance>> = Mutex::new(None);
+
+#[doc(hidden)]
+pub unsafe fn initialize_internal_client<F>(
+    client: *mut libc::c_void,
+    load_init: *const libc::c_char,
+    init: F)
+    -> libc::c_int
+    where F: FnOnce(&mut Client<'static>, &InitArgs) -> Result<(), status::Status>
+{
+    let mut instance = INSTANCE.lock().unwrap_or_else(|e| e.into_inner());
+    if instance.is_some() {
+        error!("an instance of this internal client is already loaded");
+        return 1;
+    }
+
+    let mut c = Box::new(Client::from_unowned(client as *mut jack_sys::jack_client_t));
+    let args = InitArgs::new(&copy_jack_string(load_init).unwrap_or_default());
+
+    match panic::catch_unwind(AssertUnwindSafe(|| init(&mut c, &args))) {
+        Ok(Ok(())) => (),
+        Ok(Err(s)) => {
+            error!("internal client initialization failed: {:?}", s);
+            return 1;
+        },
+        Err(_) => {
+            error!("internal client initialization panicked");
+            return 1;
+        },
+    }
+
+    if c.activate().is_err() {
+        error!("could not activate internal client");
+        return 1;
+    }
+
+    *instance = Some(Instance(c));
+    0
+}
+
+#[doc(hidden)]
+pub unsafe fn finish_internal_client() {
+    let instance = INSTANCE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    drop(instance);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_init_strings() {
+        assert_eq!(split_args(""), Vec::<String>::new());
+        assert_eq!(split_args("  -i 2  -o 2 "), vec!["-i", "2", "-o", "2"]);
+        assert_eq!(split_args("-n 'my client' --path=\"a b\""),
+                   vec!["-n", "my client", "--path=a b"]);
+        assert_eq!(split_args("a\\ b '' c"), vec!["a b", "", "c"]);
+    }
+
+    #[test]
+    fn parses_with_getopts() {
+        let mut opts = getopts::Options::new();
+        opts.optopt("i", "inputs", "", "N");
+        opts.optflag("v", "verbose", "");
+
+        let args = InitArgs::new("-i 4 -v extra");
+        let m = args.parse(&opts).unwrap();
+        assert_eq!(m.opt_str("i"), Some("4".to_string()));
+        assert!(m.opt_present("v"));
+        assert_eq!(m.free, vec!["extra"]);
+        assert_eq!(args.raw(), "-i 4 -v extra");
+    }
+}
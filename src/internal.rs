@@ -0,0 +1,43 @@
+use jack_sys;
+
+use types::*;
+
+/// A client running inside the JACK server process, such as `netmanager` or `audioadapter`.
+///
+/// Internal clients are loaded from shared objects in the server's driver directory with
+/// `Client::load_internal_client`. They keep running after the client which loaded them closes,
+/// until they are unloaded with `Client::unload_internal_client`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternalClient {
+    handle: jack_sys::jack_intclient_t,
+    name:   String,
+}
+
+impl InternalClient {
+    pub(crate) fn new(handle: jack_sys::jack_intclient_t, name: String) -> Self {
+        InternalClient { handle: handle, name: name }
+    }
+
+    /// Returns the name of the internal client (which may differ from the name requested when
+    /// it was loaded, unless `USE_EXACT_NAME` was given)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the server's handle for the internal client
+    pub fn handle(&self) -> jack_sys::jack_intclient_t {
+        self.handle
+    }
+}
+
+/// turns the handle and status returned by jack into an error if the handle is not valid
+pub(crate) fn check_handle(handle: jack_sys::jack_intclient_t, status: jack_sys::jack_status_t)
+    -> Result<(), status::Status>
+{
+    let status = status::Status::from_bits_truncate(status);
+    if handle == 0 {
+        Err(if status.is_empty() { status::FAILURE } else { status })
+    } else {
+        Ok(())
+    }
+}
@@ -1,19 +1,68 @@
 #[macro_use]
 extern crate bitflags;
+extern crate getopts;
 extern crate jack_sys;
 extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate num;
 
 // all the modules
+mod bus;
 mod client;
+mod fixedblock;
+mod inprocess;
 mod callbackhandler;
+mod cycle;
+mod ffi;
+mod internal;
+mod library;
+mod metadata;
+#[cfg(feature = "mock")]
+mod mockbackend;
 mod port;
+mod render;
 mod types;
 mod midi;
+mod offline;
+mod ring;
+mod rtthread;
+mod stats;
+mod threadinit;
+mod uuid;
+mod watchdog;
+#[cfg(all(feature = "dlopen", not(feature = "mock")))]
+mod weakjack;
+
+// modules which are not pulled into the top level namespace
+#[cfg(feature = "alloc-detector")]
+pub mod allocdetect;
+pub mod convert;
+pub mod messages;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod params;
+pub mod rtlog;
+#[cfg(feature = "server")]
+pub mod server;
 
 // get everything into this namespace
+pub use bus::*;
 pub use callbackhandler::*;
 pub use client::*;
+pub use cycle::*;
+pub use fixedblock::*;
+pub use inprocess::*;
+pub use internal::*;
+pub use library::*;
+pub use metadata::*;
 pub use midi::*;
+pub use offline::*;
 pub use port::*;
+pub use render::*;
+pub use rtthread::*;
+pub use stats::*;
+pub use threadinit::*;
 pub use types::*;
+pub use uuid::*;
+pub use watchdog::*;
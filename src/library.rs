@@ -0,0 +1,41 @@
+use std::error;
+use std::fmt;
+
+/// The ways getting hold of libjack can fail
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// libjack is not installed, or could not be loaded. Holds the dynamic loader's explanation.
+    LibraryNotFound(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::LibraryNotFound(ref why) => write!(f, "could not load libjack: {}", why),
+        }
+    }
+}
+
+impl error::Error for Error { }
+
+/// Makes sure libjack is available.
+///
+/// With the `dlopen` feature, libjack is loaded the first time it is needed rather than when the
+/// program starts, so a program can run without JACK installed. Opening a client then fails with
+/// `status::LIBRARY_NOT_FOUND` (instead of the `SERVER_FAILED` a missing server gives), and this
+/// returns `Error::LibraryNotFound` with the dynamic loader's explanation.
+///
+/// Without the feature, libjack was linked when the program was built, so this always succeeds.
+pub fn load_library() -> Result<(), Error> {
+    load()
+}
+
+#[cfg(all(feature = "dlopen", not(feature = "mock")))]
+fn load() -> Result<(), Error> {
+    ::weakjack::library().map_err(Error::LibraryNotFound)
+}
+
+#[cfg(not(all(feature = "dlopen", not(feature = "mock"))))]
+fn load() -> Result<(), Error> {
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! This module controls what happens to the error and info messages printed by libjack.
+//!
+//! By default libjack prints its messages to stderr. After `route_to_log` is called, they are
+//! forwarded to the `log` crate instead, with the target `"jack"`. Error messages are logged at
+//! the `Error` level and info messages at the `Info` level. (`tracing` users can pick these up
+//! with the `tracing-log` bridge.)
+//!
+//! Tests which want to check for particular messages can use `capture` to collect them in a
+//! buffer instead.
+//!
+//! The handlers are process wide, so these functions affect every client in the process. They
+//! should be called before any clients are opened.
+
+use ffi;
+use libc;
+use std::ffi::CStr;
+use std::sync::Mutex;
+
+/// The target JACK's messages are logged with
+pub const TARGET: &'static str = "jack";
+
+/// The kind of a message printed by libjack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    Error,
+    Info,
+}
+
+/// A message printed by libjack
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub kind: MessageKind,
+    pub text: String,
+}
+
+// when this is Some, messages are collected here instead of being logged
+static CAPTURED: Mutex<Option<Vec<Message>>> = Mutex::new(None);
+
+/// Forwards all of libjack's messages to the `log` crate.
+pub fn route_to_log() {
+    unsafe {
+        ffi::jack_set_error_function(Some(error_handler));
+        ffi::jack_set_info_function(Some(info_handler));
+    }
+}
+
+/// Starts collecting all of libjack's messages in a buffer. The messages can be read from the
+/// returned `MessageCapture`. When it is dropped, messages are forwarded to the `log` crate again.
+///
+/// Only one capture can be active at a time; starting a new capture throws away anything the
+/// previous one collected.
+pub fn capture() -> MessageCapture {
+    *lock() = Some(Vec::new());
+    route_to_log();
+    MessageCapture { _private: () }
+}
+
+/// An active capture of libjack's messages. See `capture`.
+pub struct MessageCapture {
+    _private: (),
+}
+
+impl MessageCapture {
+    /// Returns a copy of the messages collected so far
+    pub fn messages(&self) -> Vec<Message> {
+        lock().as_ref().map(|v| v.clone()).unwrap_or_default()
+    }
+
+    /// Returns the messages collected so far and clears the buffer
+    pub fn take(&self) -> Vec<Message> {
+        lock().as_mut().map(|v| v.drain(..).collect()).unwrap_or_default()
+    }
+}
+
+impl Drop for MessageCapture {
+    fn drop(&mut self) {
+        *lock() = None;
+    }
+}
+
+// a panic while the lock was held doesn't leave the buffer in a bad state, so poisoning is ignored
+fn lock() -> ::std::sync::MutexGuard<'static, Option<Vec<Message>>> {
+    match CAPTURED.lock() {
+        Ok(g)  => g,
+        Err(e) => e.into_inner(),
+    }
+}
+
+fn forward(kind: MessageKind, msg: *const libc::c_char) {
+    if msg.is_null() {
+        return;
+    }
+
+    let text = unsafe { CStr::from_ptr(msg) }.to_string_lossy();
+    let text = text.trim_end();
+
+    let mut captured = lock();
+    match captured.as_mut() {
+        Some(v) => v.push(Message { kind: kind, text: text.to_string() }),
+        None    => match kind {
+            MessageKind::Error => error!(target: TARGET, "{}", text),
+            MessageKind::Info  => info!(target: TARGET, "{}", text),
+        },
+    }
+}
+
+unsafe extern "C" fn error_handler(msg: *const libc::c_char) {
+    forward(MessageKind::Error, msg);
+}
+
+unsafe extern "C" fn info_handler(msg: *const libc::c_char) {
+    forward(MessageKind::Info, msg);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn captures_messages() {
+        let cap = capture();
+
+        let err = CString::new("cannot connect to server\n").unwrap();
+        let info = CString::new("jack server is not running").unwrap();
+        unsafe {
+            error_handler(err.as_ptr());
+            info_handler(info.as_ptr());
+        }
+
+        let expected = vec![
+            Message { kind: MessageKind::Error, text: "cannot connect to server".to_string() },
+            Message { kind: MessageKind::Info, text: "jack server is not running".to_string() },
+        ];
+
+        assert_eq!(cap.messages(), expected);
+        assert_eq!(cap.take(), expected);
+        assert_eq!(cap.messages(), vec![]);
+
+        drop(cap);
+        unsafe { error_handler(err.as_ptr()) };
+        assert!(lock().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use ffi;
+use jack_sys;
+use libc;
+
+use std::ffi::CStr;
+
+/// This module contains constants for the well-known metadata keys defined by JACK.
+///
+/// Any string may be used as a key, but these are the keys which other JACK applications are
+/// likely to understand.
+pub mod metadata_keys {
+    // these are defined in the jack source but are not exported in a form that jack_sys can use
+
+    /// A human readable name for the subject (client or port), for display purposes.
+    pub const PRETTY_NAME: &'static str = "http://jackaudio.org/metadata/pretty-name";
+
+    /// A value which identifies the physical hardware a port corresponds to.
+    pub const HARDWARE: &'static str = "http://jackaudio.org/metadata/hardware";
+
+    /// Set on a port to the name of the port it is connected to.
+    pub const CONNECTED: &'static str = "http://jackaudio.org/metadata/connected";
+
+    /// A name identifying a group of ports which belong together (for example the two channels of
+    /// a stereo pair).
+    pub const PORT_GROUP: &'static str = "http://jackaudio.org/metadata/port-group";
+
+    /// The type of signal carried by an audio port. Either "AUDIO" or "CV".
+    pub const SIGNAL_TYPE: &'static str = "http://jackaudio.org/metadata/signal-type";
+
+    /// The types of events carried by an event (midi) port, for example "MIDI" or "OSC".
+    pub const EVENT_TYPES: &'static str = "http://jackaudio.org/metadata/event-types";
+
+    /// An integer giving the order in which a client's ports should be displayed.
+    pub const ORDER: &'static str = "http://jackaudio.org/metadata/order";
+
+    /// A small (16x16) PNG icon, base64 encoded.
+    pub const ICON_SMALL: &'static str = "http://jackaudio.org/metadata/icon-small";
+
+    /// A large (32x32) PNG icon, base64 encoded.
+    pub const ICON_LARGE: &'static str = "http://jackaudio.org/metadata/icon-large";
+
+    /// The name of an icon following the freedesktop.org icon naming specification.
+    pub const ICON_NAME: &'static str = "http://jackaudio.org/metadata/icon-name";
+}
+
+/// A single metadata property, as stored by the JACK server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    /// The key identifying the property. Usually a URI, see `metadata_keys`
+    pub key: String,
+
+    /// The value of the property
+    pub value: String,
+
+    /// The type of the value, either a MIME type or a URI. If no type is given, the value is
+    /// assumed to be a plain UTF-8 string
+    pub property_type: Option<String>,
+}
+
+/// Used by the property change callback to describe what happened to a property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyChange {
+    Created,
+    Changed,
+    Deleted,
+}
+
+impl PropertyChange {
+    #[doc(hidden)]
+    pub fn from_raw(change: jack_sys::jack_property_change_t) -> Self {
+        match change {
+            jack_sys::PropertyCreated => PropertyChange::Created,
+            jack_sys::PropertyChanged => PropertyChange::Changed,
+            _                         => PropertyChange::Deleted,
+        }
+    }
+}
+
+/// copies a string owned by jack, returning None if the pointer is null
+pub(crate) unsafe fn copy_jack_string(ptr: *const libc::c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+}
+
+/// copies a string which was allocated by jack, then frees jack's copy
+pub(crate) unsafe fn take_jack_string(ptr: *mut libc::c_char) -> Option<String> {
+    let s = copy_jack_string(ptr);
+    if !ptr.is_null() {
+        ffi::jack_free(ptr as *mut libc::c_void);
+    }
+
+    s
+}
+
+/// Copies all of the properties out of a description filled in by jack, then frees the memory
+/// jack allocated for the properties (but not the description itself).
+pub(crate) unsafe fn take_description(desc: &mut jack_sys::jack_description_t) -> Vec<Property> {
+    let mut props = Vec::with_capacity(desc.property_cnt as usize);
+
+    for i in 0..desc.property_cnt as isize {
+        let p = &*desc.properties.offset(i);
+        props.push(Property {
+            key:           copy_jack_string(p.key).unwrap_or_default(),
+            value:         copy_jack_string(p.data).unwrap_or_default(),
+            property_type: copy_jack_string(p._type),
+        });
+    }
+
+    ffi::jack_free_description(desc, 0);
+    props
+}
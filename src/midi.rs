@@ -1,3 +1,4 @@
+use ffi;
 use jack_sys;
 use libc;
 use std::marker::PhantomData;
@@ -7,10 +8,13 @@ use types::*;
 
 pub struct MidiEventBuf<'a> {
     num: usize,
-    all_events_buffer: *mut libc::c_void,
+    source: Source<'a>,
+}
 
-    // only exists to enforce the lifetime
-    phantom: PhantomData<&'a libc::c_void>
+// where a MidiEventBuf gets its events from
+enum Source<'a> {
+    Jack(*mut libc::c_void, PhantomData<&'a libc::c_void>),
+    Host(&'a MidiBuffer),
 }
 
 impl<'a> MidiEventBuf<'a> {
@@ -19,36 +23,177 @@ impl<'a> MidiEventBuf<'a> {
         assert!(!jackptr.is_null());
 
         MidiEventBuf {
-            num: jack_sys::jack_midi_get_event_count(jackptr) as usize,
-            all_events_buffer: jackptr,
-            phantom: PhantomData
+            num: ffi::jack_midi_get_event_count(jackptr) as usize,
+            source: Source::Jack(jackptr, PhantomData),
+        }
+    }
+
+    pub(crate) fn from_host(buffer: &'a MidiBuffer) -> Self {
+        MidiEventBuf {
+            num: buffer.events().len(),
+            source: Source::Host(buffer),
         }
     }
 
     /// This looks like it isn't a reference, but it is. Trust me.
     pub fn get(&self, index: usize) -> MidiEventRef {
-        assert!(!self.all_events_buffer.is_null());
-
         if index >= self.num {
             panic!("index out of bounds");
         }
 
-        unsafe {
-            let mut jstruct = mem::uninitialized();
-            let ret = jack_sys::jack_midi_event_get(
-                &mut jstruct,
-                self.all_events_buffer,
-                index as u32);
+        match self.source {
+            Source::Jack(ptr, _) => unsafe {
+                assert!(!ptr.is_null());
+
+                let mut jstruct = mem::uninitialized();
+                let ret = ffi::jack_midi_event_get(&mut jstruct, ptr, index as u32);
+
+                if ret != 0 {
+                    panic!("index out of bounds/ENODATA");
+                }
+
+                MidiEventRef::new(jstruct)
+            },
+
+            Source::Host(buffer) => {
+                let (time, ref data) = buffer.events()[index];
+                MidiEventRef {
+                    time:    time,
+                    len:     data.len(),
+                    buffer:  data.as_ptr() as *mut jack_sys::jack_midi_data_t,
+                    phantom: PhantomData,
+                }
+            },
+        }
+    }
 
-            if ret != 0 {
-                panic!("index out of bounds/ENODATA");
-            }
+    pub fn len(&self) -> usize { self.num }
+}
+
+/// A writer for the midi events sent out of an output port during a single cycle.
+///
+/// Events must be written in order of their frame time.
+pub struct MidiEventWriter<'a> {
+    sink: Sink<'a>,
+}
 
-            MidiEventRef::new(jstruct)
+// where a MidiEventWriter puts its events. There may be more than one writer for a port, which is
+// why the host's buffer is held by pointer like jack's is
+enum Sink<'a> {
+    Jack(*mut libc::c_void, PhantomData<&'a libc::c_void>),
+    Host(*mut MidiBuffer, PhantomData<&'a MidiBuffer>),
+}
+
+impl<'a> MidiEventWriter<'a> {
+    #[doc(hidden)]
+    pub unsafe fn new(jackptr: *mut libc::c_void) -> Self {
+        assert!(!jackptr.is_null());
+
+        // jack requires that the buffer is cleared every cycle before anything is written to it
+        ffi::jack_midi_clear_buffer(jackptr);
+
+        MidiEventWriter {
+            sink: Sink::Jack(jackptr, PhantomData),
         }
     }
 
-    pub fn len(&self) -> usize { self.num }
+    /// `buffer` must stay valid for `'a`, and must only be used from this thread meanwhile
+    pub(crate) unsafe fn to_host(buffer: *mut MidiBuffer) -> Self {
+        (*buffer).clear();
+        MidiEventWriter {
+            sink: Sink::Host(buffer, PhantomData),
+        }
+    }
+
+    /// Writes a single midi event at the given frame offset into the current cycle.
+    ///
+    /// Fails if the event is out of order, `time` is past the end of the cycle, or there is not
+    /// enough room left in the buffer.
+    pub fn write(&mut self, time: NumFrames, data: &[u8]) -> Result<(), status::Status> {
+        let ret = match self.sink {
+            Sink::Jack(ptr, _) => unsafe {
+                ffi::jack_midi_event_write(ptr, time, data.as_ptr(), data.len())
+            },
+            Sink::Host(buffer, _) => unsafe { (*buffer).push(time, data) },
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(status::FAILURE)
+        }
+    }
+
+    /// Returns the size of the largest event which can still be written this cycle
+    pub fn max_event_size(&self) -> usize {
+        match self.sink {
+            Sink::Jack(ptr, _)    => unsafe { ffi::jack_midi_max_event_size(ptr) },
+            Sink::Host(buffer, _) => unsafe { (*buffer).max_event_size() },
+        }
+    }
+
+    /// Returns the number of events which could not be written to this buffer (because it was
+    /// full) since the last time the buffer was cleared
+    pub fn lost_event_count(&self) -> u32 {
+        match self.sink {
+            Sink::Jack(ptr, _)    => unsafe { ffi::jack_midi_get_lost_event_count(ptr) },
+            Sink::Host(buffer, _) => unsafe { (*buffer).lost() },
+        }
+    }
+}
+
+/// An in-memory midi buffer which follows the same rules as a JACK midi port buffer: events must
+/// be written in order, must fall inside the cycle, and must fit in the space JACK would give the
+/// port. Used for ports which are not backed by JACK (see `OfflineHost`).
+pub(crate) struct MidiBuffer {
+    nframes: NumFrames,
+    used:    usize,
+    lost:    u32,
+    events:  Vec<(NumFrames, Vec<u8>)>,
+}
+
+// bytes jack stores alongside the data of each event
+const MIDI_EVENT_OVERHEAD: usize = 8;
+
+impl MidiBuffer {
+    pub(crate) fn new(nframes: NumFrames) -> Self {
+        MidiBuffer { nframes: nframes, used: 0, lost: 0, events: Vec::new() }
+    }
+
+    // jack gives midi ports the same number of bytes as audio ports
+    fn capacity(&self) -> usize {
+        self.nframes as usize * mem::size_of::<DefaultAudioSample>()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.used = 0;
+        self.lost = 0;
+        self.events.clear();
+    }
+
+    /// Adds an event, returning 0 or the (negated) errno `jack_midi_event_write` would
+    pub(crate) fn push(&mut self, time: NumFrames, data: &[u8]) -> libc::c_int {
+        if time >= self.nframes || self.events.last().map_or(false, |e| e.0 > time) {
+            return -libc::EINVAL;
+        }
+
+        if self.used + data.len() + MIDI_EVENT_OVERHEAD > self.capacity() {
+            self.lost += 1;
+            return -libc::ENOBUFS;
+        }
+
+        self.used += data.len() + MIDI_EVENT_OVERHEAD;
+        self.events.push((time, data.to_vec()));
+        0
+    }
+
+    pub(crate) fn events(&self) -> &[(NumFrames, Vec<u8>)] { &self.events }
+
+    pub(crate) fn lost(&self) -> u32 { self.lost }
+
+    pub(crate) fn max_event_size(&self) -> usize {
+        self.capacity().saturating_sub(self.used + MIDI_EVENT_OVERHEAD)
+    }
 }
 
 /// A structure representing a midi event
@@ -0,0 +1,508 @@
+//! An in-process stand-in for a JACK server, for unit testing handlers without running `jackd`.
+//! Only available with the `mock` feature, which sends every call this crate makes into libjack
+//! to the mock instead. (libjack is still linked, since jack-sys always links it, but it is never
+//! called.)
+//!
+//! A `MockServer` holds a port graph. Clients opened with `Client::open` on the thread which
+//! created the server (or with `Client::open_connection_to` and the server's `name()`, from any
+//! thread) join it and behave as they would on a real server, except that no cycles run until the
+//! test asks for them:
+//!
+//! ```ignore
+//! let server = MockServer::new();
+//! server.add_port("system:capture_1", port_type::DEFAULT_AUDIO_TYPE, PortDirection::Output);
+//! server.add_port("system:playback_1", port_type::DEFAULT_AUDIO_TYPE, PortDirection::Input);
+//!
+//! let (mut client, _) = Client::open("gain", options::NO_START_SERVER).unwrap();
+//! // register ports, set a process handler, activate, connect to the system ports...
+//!
+//! server.write_audio("system:capture_1", &[0.5; 256]);
+//! server.run_cycles(1);
+//! assert_eq!(server.read_audio("system:playback_1"), vec![1.0; 256]);
+//! ```
+//!
+//! Where it differs from a real server:
+//!
+//! - Callbacks run synchronously, on whichever thread caused them: process (and thread init)
+//!   callbacks from `run_cycles`, port connect callbacks from whoever connected the ports, and so
+//!   on. As with JACK, only active clients are notified.
+//! - Each cycle runs the clients one after another, in an order where every client runs after the
+//!   clients connected to its inputs.
+//! - Nothing runs realtime, `set_process_thread` is not supported, and internal clients can not
+//!   be loaded.
+//! - Port name and type patterns are matched as plain substrings, not regular expressions.
+//! - Clients are not told when ports or other clients come and go, since easyjack does not install
+//!   the port and client registration callbacks yet. They are told about the connections which are
+//!   broken by it.
+//! - Properties belong to a server. Property lookups which are not given a client use the
+//!   thread's current server.
+
+use mockbackend::{self, Shared};
+use types::*;
+
+/// A fake JACK server. Clones refer to the same server, which is shut down once the last clone
+/// is dropped (the clients connected to it must be closed first).
+#[derive(Clone)]
+pub struct MockServer {
+    graph: Shared,
+}
+
+impl MockServer {
+    /// Creates a server running at 48kHz with 256 frame buffers, and makes it current on this
+    /// thread
+    pub fn new() -> Self {
+        Self::with_config(48000, 256)
+    }
+
+    /// Creates a server with the given sample rate and buffer size, and makes it current on this
+    /// thread
+    pub fn with_config(sample_rate: NumFrames, buffer_size: NumFrames) -> Self {
+        let server = MockServer { graph: mockbackend::new_graph(sample_rate, buffer_size) };
+        server.make_current();
+        server
+    }
+
+    /// The name to pass to `Client::open_connection_to` to connect to this server
+    pub fn name(&self) -> String {
+        mockbackend::lock(&self.graph).name().to_string()
+    }
+
+    /// Makes this the server that `Client::open` connects to on the calling thread
+    pub fn make_current(&self) {
+        mockbackend::make_current(&self.graph)
+    }
+
+    pub fn sample_rate(&self) -> NumFrames {
+        mockbackend::lock(&self.graph).sample_rate()
+    }
+
+    pub fn buffer_size(&self) -> NumFrames {
+        mockbackend::lock(&self.graph).buffer_size()
+    }
+
+    /// The frame time at the start of the next cycle
+    pub fn frame_time(&self) -> NumFrames {
+        mockbackend::lock(&self.graph).frame_time()
+    }
+
+    /// Adds a physical port owned by the server, like the ones a real server creates for the
+    /// sound card. Data written to output ports is fed to the graph, and data reaching input ports
+    /// is collected for `read_audio` and `read_midi`.
+    ///
+    /// Returns false if the name is taken or the type is not one of the default types.
+    pub fn add_port(&self, name: &str, ptype: PortType, direction: PortDirection) -> bool {
+        let is_default = ptype == port_type::DEFAULT_AUDIO_TYPE
+            || ptype == port_type::DEFAULT_MIDI_TYPE;
+
+        let mut g = mockbackend::lock(&self.graph);
+        is_default && g.add_system_port(&self.graph, name, ptype, direction).is_some()
+    }
+
+    /// Removes one of the server's own ports, as if the sound card had gone away. The port is
+    /// disconnected first, and the clients are notified as if they had disconnected it.
+    ///
+    /// Returns false if there is no such port or it belongs to a client.
+    pub fn remove_port(&self, name: &str) -> bool {
+        let notes = mockbackend::lock(&self.graph).remove_system_port(name);
+        notes.map(mockbackend::notify).is_some()
+    }
+
+    /// The names of every client connected to the server
+    pub fn clients(&self) -> Vec<String> {
+        mockbackend::lock(&self.graph).client_names()
+    }
+
+    /// The names of every port on the server
+    pub fn ports(&self) -> Vec<String> {
+        mockbackend::lock(&self.graph).port_names()
+    }
+
+    /// The names of the ports connected to the given port
+    pub fn connections(&self, port: &str) -> Vec<String> {
+        mockbackend::lock(&self.graph).connections_of(port)
+    }
+
+    /// Connects two ports, as `Client::connect_ports` would
+    pub fn connect(&self, source: &str, destination: &str) -> Result<(), status::Status> {
+        let res = mockbackend::lock(&self.graph).connect(source, destination);
+        res.map(mockbackend::notify).map_err(|_| status::FAILURE)
+    }
+
+    /// Disconnects two ports, as `Client::disconnect_ports` would
+    pub fn disconnect(&self, source: &str, destination: &str) -> Result<(), status::Status> {
+        let res = mockbackend::lock(&self.graph).disconnect(source, destination);
+        res.map(mockbackend::notify).map_err(|_| status::FAILURE)
+    }
+
+    /// Queues samples to be played out of one of the server's output audio ports, starting with
+    /// the next cycle. Once the queue runs dry, the port plays silence.
+    ///
+    /// Returns false if there is no such port.
+    pub fn write_audio(&self, port: &str, samples: &[DefaultAudioSample]) -> bool {
+        mockbackend::lock(&self.graph).queue_audio(port, samples)
+    }
+
+    /// Takes everything which reached one of the server's input audio ports since the last call
+    ///
+    /// Returns an empty vector if there is no such port.
+    pub fn read_audio(&self, port: &str) -> Vec<DefaultAudioSample> {
+        mockbackend::lock(&self.graph).take_audio(port).unwrap_or_default()
+    }
+
+    /// Queues a midi event to be sent out of one of the server's output midi ports, `offset`
+    /// frames after the start of the next cycle
+    ///
+    /// Returns false if there is no such port.
+    pub fn write_midi(&self, port: &str, offset: NumFrames, data: &[u8]) -> bool {
+        mockbackend::lock(&self.graph).queue_midi(port, offset, data)
+    }
+
+    /// Takes every midi event which reached one of the server's input midi ports since the last
+    /// call, with the frame time each one arrived at
+    ///
+    /// Returns an empty vector if there is no such port.
+    pub fn read_midi(&self, port: &str) -> Vec<(NumFrames, Vec<u8>)> {
+        mockbackend::lock(&self.graph).take_midi(port).unwrap_or_default()
+    }
+
+    /// Runs `n` process cycles. A client whose process callback returns nonzero is deactivated,
+    /// as JACK would.
+    pub fn run_cycles(&self, n: usize) {
+        mockbackend::run_cycles(&self.graph, n)
+    }
+
+    /// Reports an xrun to every active client. `delay` is what `jack_get_xrun_delayed_usecs`
+    /// returns afterwards.
+    pub fn xrun(&self, delay: f32) {
+        let notes = mockbackend::lock(&self.graph).xrun(delay);
+        mockbackend::notify(notes)
+    }
+
+    /// Changes the sample rate and notifies every active client
+    pub fn set_sample_rate(&self, rate: NumFrames) {
+        let notes = mockbackend::lock(&self.graph).set_sample_rate(rate);
+        mockbackend::notify(notes)
+    }
+
+    /// Acts as if the server had shut down: every active client's shutdown callback is called,
+    /// and then every client is deactivated so that it gets no more callbacks. The clients must
+    /// still be closed.
+    pub fn shutdown(&self) {
+        let notes = mockbackend::lock(&self.graph).shutdown();
+        mockbackend::notify(notes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use callbackhandler::*;
+    use client::*;
+    use midi::*;
+    use port::*;
+    use render::*;
+
+    use std::io;
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+    use std::time::Duration;
+
+    struct Gain {
+        input:  InputPortHandle<DefaultAudioSample>,
+        output: OutputPortHandle<DefaultAudioSample>,
+    }
+
+    impl ProcessHandler for Gain {
+        fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+            let i = self.input.get_read_buffer(nframes, ctx);
+            let o = self.output.get_write_buffer(nframes, ctx);
+            for (o, i) in o.iter_mut().zip(i.iter()) {
+                *o = *i * 2.0;
+            }
+
+            0
+        }
+    }
+
+    fn system_audio(server: &MockServer) {
+        let audio = port_type::DEFAULT_AUDIO_TYPE;
+        assert!(server.add_port("system:capture_1", audio, PortDirection::Output));
+        assert!(server.add_port("system:playback_1", audio, PortDirection::Input));
+    }
+
+    #[test]
+    fn audio_passthrough() {
+        let server = MockServer::with_config(48000, 4);
+        system_audio(&server);
+
+        let (mut client, name) = Client::open("gain", options::NO_START_SERVER).unwrap();
+        assert_eq!(name, "gain");
+
+        let input = client.register_input_audio_port("in").unwrap();
+        let output = client.register_output_audio_port("out").unwrap();
+        client.set_process_handler(Gain { input: input, output: output }).unwrap();
+        client.activate().unwrap();
+
+        client.connect_ports("system:capture_1", "gain:in").unwrap();
+        server.connect("gain:out", "system:playback_1").unwrap();
+        assert_eq!(server.connections("gain:in"), vec!["system:capture_1".to_string()]);
+
+        server.write_audio("system:capture_1", &[0.25, 0.5, 0.0, 1.0, -1.0]);
+        server.run_cycles(2);
+
+        let out = server.read_audio("system:playback_1");
+        assert_eq!(out, vec![0.5, 1.0, 0.0, 2.0, -2.0, 0.0, 0.0, 0.0]);
+        assert_eq!(server.frame_time(), 8);
+
+        client.close().unwrap();
+        assert!(server.clients().is_empty());
+        assert_eq!(server.ports().len(), 2);
+    }
+
+    struct Transpose {
+        input:  InputPortHandle<MidiEvent>,
+        output: OutputPortHandle<MidiEvent>,
+    }
+
+    impl ProcessHandler for Transpose {
+        fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+            let events = self.input.get_read_buffer(nframes, ctx);
+            let mut writer = self.output.get_write_buffer(nframes, ctx);
+            for i in 0..events.len() {
+                let e = events.get(i);
+                let mut bytes = e.raw_midi_bytes().to_vec();
+                bytes[1] += 12;
+                writer.write(e.get_jack_time(), &bytes).unwrap();
+            }
+
+            0
+        }
+    }
+
+    #[test]
+    fn midi_round_trip() {
+        let server = MockServer::with_config(48000, 16);
+        let midi = port_type::DEFAULT_MIDI_TYPE;
+        assert!(server.add_port("system:midi_capture_1", midi, PortDirection::Output));
+        assert!(server.add_port("system:midi_playback_1", midi, PortDirection::Input));
+
+        let (mut client, _) = Client::open("transpose", options::NO_START_SERVER).unwrap();
+        let input = client.register_input_midi_port("in").unwrap();
+        let output = client.register_output_midi_port("out").unwrap();
+        client.set_process_handler(Transpose { input: input, output: output }).unwrap();
+        client.activate().unwrap();
+
+        server.connect("system:midi_capture_1", "transpose:in").unwrap();
+        server.connect("transpose:out", "system:midi_playback_1").unwrap();
+
+        server.write_midi("system:midi_capture_1", 3, &[0x90, 60, 100]);
+        server.write_midi("system:midi_capture_1", 20, &[0x80, 60, 0]);
+        server.run_cycles(2);
+
+        let expected = vec![(3, vec![0x90, 72, 100]), (20, vec![0x80, 72, 0])];
+        assert_eq!(server.read_midi("system:midi_playback_1"), expected);
+
+        client.close().unwrap();
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        SampleRate(NumFrames),
+        Connect(PortId, PortId, bool),
+        Xrun,
+    }
+
+    struct Recorder {
+        tx: Sender<Event>,
+    }
+
+    impl MetadataHandler for Recorder {
+        fn sample_rate_changed(&mut self, srate: NumFrames) -> i32 {
+            self.tx.send(Event::SampleRate(srate)).unwrap();
+            0
+        }
+
+        fn on_port_connect(&mut self, a: PortId, b: PortId, status: PortConnectStatus) {
+            let connected = match status {
+                PortConnectStatus::PortsConnected    => true,
+                PortConnectStatus::PortsDisconnected => false,
+            };
+
+            self.tx.send(Event::Connect(a, b, connected)).unwrap();
+        }
+
+        fn on_xrun(&mut self) -> i32 {
+            self.tx.send(Event::Xrun).unwrap();
+            0
+        }
+
+        fn callbacks_of_interest(&self) -> Vec<MetadataHandlers> {
+            vec![
+                MetadataHandlers::SampleRate,
+                MetadataHandlers::PortConnect,
+                MetadataHandlers::Xrun,
+            ]
+        }
+    }
+
+    #[test]
+    fn notifications() {
+        let server = MockServer::new();
+        system_audio(&server);
+
+        let (tx, rx) = mpsc::channel();
+        let (mut client, _) =
+            Client::open_connection_to("watcher", &server.name(), options::NO_START_SERVER)
+                .unwrap();
+
+        client.set_metadata_handler(Recorder { tx: tx }).unwrap();
+
+        // the sample rate callback is called as soon as it is set
+        assert_eq!(rx.try_recv(), Ok(Event::SampleRate(48000)));
+
+        // inactive clients are not notified
+        server.xrun(1.0);
+        assert!(rx.try_recv().is_err());
+
+        client.activate().unwrap();
+        server.connect("system:capture_1", "system:playback_1").unwrap();
+        match rx.try_recv() {
+            Ok(Event::Connect(a, b, true)) => {
+                assert_eq!(client.get_port_by_id(a).unwrap().get_name(), "system:capture_1");
+                assert_eq!(client.get_port_by_id(b).unwrap().get_name(), "system:playback_1");
+            },
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        server.xrun(2.0);
+        assert_eq!(rx.try_recv(), Ok(Event::Xrun));
+
+        server.set_sample_rate(44100);
+        assert_eq!(rx.try_recv(), Ok(Event::SampleRate(44100)));
+        assert_eq!(client.get_sample_rate(), 44100);
+
+        // removing a port breaks its connections
+        assert!(server.remove_port("system:playback_1"));
+        assert!(!server.remove_port("system:playback_1"));
+        match rx.try_recv() {
+            Ok(Event::Connect(_, _, false)) => (),
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert!(server.connections("system:capture_1").is_empty());
+        assert_eq!(server.ports(), vec!["system:capture_1".to_string()]);
+
+        client.close().unwrap();
+    }
+
+    #[test]
+    fn client_names() {
+        let server = MockServer::new();
+
+        let (mut a, _) = Client::open("dup", options::NO_START_SERVER).unwrap();
+        let (mut b, name) = Client::open("dup", options::NO_START_SERVER).unwrap();
+        assert_eq!(name, "dup-01");
+
+        let exact = options::NO_START_SERVER | options::USE_EXACT_NAME;
+        assert!(Client::open("dup", exact).is_err());
+        assert_eq!(server.clients(), vec!["dup".to_string(), "dup-01".to_string()]);
+
+        b.close().unwrap();
+        a.close().unwrap();
+    }
+
+    #[test]
+    fn properties() {
+        let _server = MockServer::new();
+
+        let (mut client, _) = Client::open("meta", options::NO_START_SERVER).unwrap();
+        let port = client.register_output_audio_port("out").unwrap();
+        let me = client.uuid().unwrap();
+
+        client.set_property(me, "k1", "a", None).unwrap();
+        client.set_property(me, "k2", "b", Some("text/plain")).unwrap();
+        client.set_property(port.get_uuid(), "k1", "c", None).unwrap();
+
+        let mine = client.get_properties(me);
+        assert_eq!(mine.len(), 2);
+        assert_eq!(mine[1].property_type, Some("text/plain".to_string()));
+
+        let mut counts: Vec<usize> =
+            client.get_all_properties().iter().map(|&(_, ref p)| p.len()).collect();
+        counts.sort();
+        assert_eq!(counts, vec![1, 2]);
+
+        client.close().unwrap();
+    }
+
+    struct Collect(Vec<DefaultAudioSample>);
+
+    impl RenderSink for Collect {
+        fn write(&mut self, channels: &[&[DefaultAudioSample]]) -> io::Result<()> {
+            self.0.extend_from_slice(channels[0]);
+            Ok(())
+        }
+    }
+
+    // renders `frames` frames of system:capture_1 on another thread, returning once the render's
+    // client is connected
+    fn start_render(server: &MockServer, frames: u64)
+        -> thread::JoinHandle<Result<Collect, RenderError>>
+    {
+        let remote = server.clone();
+        let duration = Duration::from_nanos(frames * 1_000_000_000 / server.sample_rate() as u64);
+        let render = thread::spawn(move || {
+            remote.make_current();
+            render_offline(duration, &["system:capture_1"], Collect(Vec::new()))
+        });
+
+        while server.connections("system:capture_1").is_empty() {
+            thread::yield_now();
+        }
+
+        render
+    }
+
+    #[test]
+    fn render_while_freewheeling() {
+        let server = MockServer::with_config(48000, 16);
+        system_audio(&server);
+
+        // no freewheel notification is sent when the render asks for freewheel mode
+        let (mut other, _) = Client::open("other", options::NO_START_SERVER).unwrap();
+        other.set_freewheel(true).unwrap();
+
+        let render = start_render(&server, 40);
+        while !render.is_finished() {
+            server.run_cycles(1);
+        }
+
+        assert_eq!(render.join().unwrap().unwrap().0.len(), 40);
+        other.close().unwrap();
+    }
+
+    #[test]
+    fn render_server_shutdown() {
+        let server = MockServer::new();
+        system_audio(&server);
+
+        let render = start_render(&server, 48000);
+        server.shutdown();
+
+        match render.join().unwrap() {
+            Err(RenderError::Interrupted) => (),
+            r => panic!("unexpected result {:?}", r.map(|c| c.0.len())),
+        }
+
+        assert!(server.clients().is_empty());
+    }
+
+    #[test]
+    fn no_server() {
+        // a fresh thread has no current server
+        let res = ::std::thread::spawn(|| {
+            Client::open("lonely", options::NO_START_SERVER).map(|_| ()).unwrap_err()
+        }).join().unwrap();
+
+        assert!(res.contains(status::SERVER_FAILED));
+    }
+}
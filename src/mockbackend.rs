@@ -0,0 +1,1662 @@
+//! The in-process stand-ins for the libjack functions used by this crate, compiled in with the
+//! `mock` feature (see the `mock` module for the scripting side). Every function here has the same
+//! signature as the jack_sys function it replaces, so `ffi` can re-export either set.
+//!
+//! A `jack_client_t` pointer handed out here really points at a `MockClient`, and a `jack_port_t`
+//! pointer at a `MockPort`. Both hold on to the graph they belong to. The graph is only locked
+//! while it is being read or changed; callbacks are always run with the lock released, since
+//! they usually call straight back in.
+
+use jack_sys;
+use jack_sys::{jack_client_t, jack_port_t, jack_description_t, jack_property_t};
+use libc;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use midi::MidiBuffer;
+use types::*;
+use uuid::*;
+
+pub(crate) type Shared = Arc<Mutex<Graph>>;
+
+// the servers clients can connect to by name
+static SERVERS: Mutex<Vec<(String, Weak<Mutex<Graph>>)>> = Mutex::new(Vec::new());
+
+// uuids are unique across every mock server in the process
+static NEXT_UUID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // the server clients opened without a server name connect to
+    static CURRENT: RefCell<Option<Shared>> = RefCell::new(None);
+}
+
+// the same limits libjack uses
+const PORT_NAME_SIZE: usize = 320;
+const CLIENT_NAME_SIZE: usize = 64;
+
+fn next_uuid() -> UUID {
+    NEXT_UUID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub(crate) fn lock(shared: &Shared) -> MutexGuard<'_, Graph> {
+    // a test which panics inside a callback should not take every later test down with it
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Creates a graph and makes it reachable by name
+pub(crate) fn new_graph(sample_rate: NumFrames, buffer_size: NumFrames) -> Shared {
+    let mut servers = SERVERS.lock().unwrap_or_else(|e| e.into_inner());
+    servers.retain(|&(_, ref g)| g.upgrade().is_some());
+
+    let name = format!("mock-{}", next_uuid());
+    let shared = Arc::new(Mutex::new(Graph::new(name.clone(), sample_rate, buffer_size)));
+    servers.push((name, Arc::downgrade(&shared)));
+    shared
+}
+
+/// Makes the graph the one clients opened on this thread without a server name connect to
+pub(crate) fn make_current(shared: &Shared) {
+    CURRENT.with(|c| *c.borrow_mut() = Some(shared.clone()));
+}
+
+fn current() -> Option<Shared> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+fn find_server(name: &str) -> Option<Shared> {
+    let servers = SERVERS.lock().unwrap_or_else(|e| e.into_inner());
+    servers.iter().filter(|&&(ref n, _)| n == name).filter_map(|&(_, ref g)| g.upgrade()).next()
+}
+
+// what a jack_client_t pointer points at
+struct MockClient {
+    graph: Shared,
+    id:    usize,
+    name:  CString,
+}
+
+// what a jack_port_t pointer points at. The graph owns these, so they only hold a weak reference
+// back to it
+struct MockPort {
+    graph: Weak<Mutex<Graph>>,
+    id:    PortId,
+}
+
+impl MockPort {
+    fn graph(&self) -> Shared {
+        self.graph.upgrade().expect("port used after its mock server was dropped")
+    }
+}
+
+unsafe fn client<'a>(c: *const jack_client_t) -> &'a MockClient {
+    &*(c as *const MockClient)
+}
+
+unsafe fn port<'a>(p: *const jack_port_t) -> &'a MockPort {
+    &*(p as *const MockPort)
+}
+
+type Arg = *mut libc::c_void;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    process:         (jack_sys::JackProcessCallback, Arg),
+    thread_init:     (jack_sys::JackThreadInitCallback, Arg),
+    sample_rate:     (jack_sys::JackSampleRateCallback, Arg),
+    port_connect:    (jack_sys::JackPortConnectCallback, Arg),
+    xrun:            (jack_sys::JackXRunCallback, Arg),
+    freewheel:       (jack_sys::JackFreewheelCallback, Arg),
+    shutdown:        (jack_sys::JackShutdownCallback, Arg),
+    latency:         (jack_sys::JackLatencyCallback, Arg),
+    property_change: (jack_sys::JackPropertyChangeCallback, Arg),
+}
+
+impl Callbacks {
+    fn new() -> Self {
+        Callbacks {
+            process:         (None, ptr::null_mut()),
+            thread_init:     (None, ptr::null_mut()),
+            sample_rate:     (None, ptr::null_mut()),
+            port_connect:    (None, ptr::null_mut()),
+            xrun:            (None, ptr::null_mut()),
+            freewheel:       (None, ptr::null_mut()),
+            shutdown:        (None, ptr::null_mut()),
+            latency:         (None, ptr::null_mut()),
+            property_change: (None, ptr::null_mut()),
+        }
+    }
+}
+
+struct ClientState {
+    name:          String,
+    uuid:          UUID,
+    active:        bool,
+    thread_inited: bool,
+    callbacks:     Callbacks,
+}
+
+enum Buffer {
+    Audio(Vec<DefaultAudioSample>),
+    Midi(Box<MidiBuffer>),
+    Other(Vec<u8>),
+}
+
+struct PortState {
+    name:      CString,
+    port_type: String,
+    flags:     libc::c_ulong,
+    owner:     Option<usize>,
+    uuid:      UUID,
+    aliases:   Vec<String>,
+    latency:   [(NumFrames, NumFrames); 2],
+    buffer:    Buffer,
+    handle:    *mut MockPort,
+
+    // only used by the server's own ports, which have no client to fill or drain them
+    audio_in:  VecDeque<DefaultAudioSample>,
+    audio_out: Vec<DefaultAudioSample>,
+    midi_in:   Vec<(NumFrames, Vec<u8>)>,
+    midi_out:  Vec<(NumFrames, Vec<u8>)>,
+}
+
+impl PortState {
+    fn is_input(&self) -> bool {
+        self.flags & jack_sys::JackPortIsInput as libc::c_ulong != 0
+    }
+
+    fn is_output(&self) -> bool {
+        self.flags & jack_sys::JackPortIsOutput as libc::c_ulong != 0
+    }
+
+    fn name(&self) -> &str {
+        self.name.to_str().unwrap()
+    }
+}
+
+struct PropertyState {
+    subject:   UUID,
+    key:       String,
+    value:     String,
+    prop_type: Option<String>,
+}
+
+/// The state of a mock server
+pub(crate) struct Graph {
+    name:        String,
+    sample_rate: NumFrames,
+    buffer_size: NumFrames,
+    frame_time:  NumFrames,
+    cycle_start: NumFrames,
+    freewheel:   bool,
+    xrun_delay:  f32,
+    clients:     Vec<Option<ClientState>>,
+    ports:       Vec<Option<PortState>>,
+
+    // (source, destination)
+    connections: Vec<(PortId, PortId)>,
+    properties:  Vec<PropertyState>,
+}
+
+// the raw pointers in the graph are only handles and callback arguments, which the graph never
+// dereferences itself
+unsafe impl Send for Graph {}
+
+impl Drop for Graph {
+    fn drop(&mut self) {
+        for id in 0..self.ports.len() as PortId {
+            self.remove_port(id);
+        }
+    }
+}
+
+impl Graph {
+    fn new(name: String, sample_rate: NumFrames, buffer_size: NumFrames) -> Self {
+        Graph {
+            name:        name,
+            sample_rate: sample_rate,
+            buffer_size: buffer_size,
+            frame_time:  0,
+            cycle_start: 0,
+            freewheel:   false,
+            xrun_delay:  0.0,
+            clients:     Vec::new(),
+            ports:       Vec::new(),
+            connections: Vec::new(),
+            properties:  Vec::new(),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str { &self.name }
+    pub(crate) fn frame_time(&self) -> NumFrames { self.frame_time }
+    pub(crate) fn sample_rate(&self) -> NumFrames { self.sample_rate }
+    pub(crate) fn buffer_size(&self) -> NumFrames { self.buffer_size }
+
+    pub(crate) fn client_names(&self) -> Vec<String> {
+        self.clients.iter().filter_map(|c| c.as_ref()).map(|c| c.name.clone()).collect()
+    }
+
+    pub(crate) fn port_names(&self) -> Vec<String> {
+        self.ports.iter().filter_map(|p| p.as_ref()).map(|p| p.name().to_string()).collect()
+    }
+
+    fn port(&self, id: PortId) -> Option<&PortState> {
+        self.ports.get(id as usize).and_then(|p| p.as_ref())
+    }
+
+    fn port_mut(&mut self, id: PortId) -> Option<&mut PortState> {
+        self.ports.get_mut(id as usize).and_then(|p| p.as_mut())
+    }
+
+    pub(crate) fn port_id(&self, name: &str) -> Option<PortId> {
+        self.ports.iter()
+            .position(|p| p.as_ref().map_or(false, |p| p.name() == name))
+            .or_else(|| {
+                self.ports.iter()
+                    .position(|p| p.as_ref().map_or(false, |p| p.aliases.iter().any(|a| a == name)))
+            })
+            .map(|i| i as PortId)
+    }
+
+    pub(crate) fn connections_of(&self, name: &str) -> Vec<String> {
+        let id = match self.port_id(name) {
+            Some(id) => id,
+            None     => return Vec::new(),
+        };
+
+        self.connections.iter()
+            .filter_map(|&(a, b)| if a == id { Some(b) } else if b == id { Some(a) } else { None })
+            .filter_map(|other| self.port(other))
+            .map(|p| p.name().to_string())
+            .collect()
+    }
+
+    fn client_by_name(&self, name: &str) -> Option<&ClientState> {
+        self.clients.iter().filter_map(|c| c.as_ref()).find(|c| c.name == name)
+    }
+
+    fn owner_active(&self, id: PortId) -> bool {
+        match self.port(id).and_then(|p| p.owner) {
+            Some(c) => self.clients[c].as_ref().map_or(false, |c| c.active),
+            None    => true,
+        }
+    }
+
+    fn add_port(
+        &mut self,
+        shared: &Shared,
+        name: String,
+        port_type: &str,
+        flags: libc::c_ulong,
+        owner: Option<usize>,
+        buffer_size: usize)
+        -> Option<PortId>
+    {
+        if name.len() >= PORT_NAME_SIZE || self.port_id(&name).is_some() {
+            return None;
+        }
+
+        let name = CString::new(name).ok()?;
+        let buffer = if port_type == port_type::DEFAULT_AUDIO_TYPE {
+            Buffer::Audio(vec![0.0; self.buffer_size as usize])
+        } else if port_type == port_type::DEFAULT_MIDI_TYPE {
+            Buffer::Midi(Box::new(MidiBuffer::new(self.buffer_size)))
+        } else if buffer_size > 0 {
+            Buffer::Other(vec![0; buffer_size])
+        } else {
+            return None;
+        };
+
+        let id = self.ports.len() as PortId;
+        let handle = Box::into_raw(Box::new(MockPort { graph: Arc::downgrade(shared), id: id }));
+
+        self.ports.push(Some(PortState {
+            name:      name,
+            port_type: port_type.to_string(),
+            flags:     flags,
+            owner:     owner,
+            uuid:      next_uuid(),
+            aliases:   Vec::new(),
+            latency:   [(0, 0); 2],
+            buffer:    buffer,
+            handle:    handle,
+            audio_in:  VecDeque::new(),
+            audio_out: Vec::new(),
+            midi_in:   Vec::new(),
+            midi_out:  Vec::new(),
+        }));
+
+        Some(id)
+    }
+
+    /// Adds one of the server's own ports
+    pub(crate) fn add_system_port(&mut self, shared: &Shared, name: &str, ptype: PortType,
+                                  direction: PortDirection) -> Option<PortId>
+    {
+        let dir = match direction {
+            PortDirection::Input  => jack_sys::JackPortIsInput,
+            PortDirection::Output => jack_sys::JackPortIsOutput,
+        };
+
+        let flags = dir | jack_sys::JackPortIsPhysical | jack_sys::JackPortIsTerminal;
+        self.add_port(shared, name.to_string(), ptype, flags as libc::c_ulong, None, 0)
+    }
+
+    /// Removes one of the server's own ports, returning the callbacks to notify of the
+    /// connections this breaks. Client ports are left alone.
+    pub(crate) fn remove_system_port(&mut self, name: &str) -> Option<Vec<Notify>> {
+        let id = self.port_id(name)?;
+        if self.port(id)?.owner.is_some() {
+            return None;
+        }
+
+        let notes = self.disconnect_all(&[id]);
+        self.remove_port(id);
+        Some(notes)
+    }
+
+    fn remove_port(&mut self, id: PortId) {
+        if let Some(p) = self.ports.get_mut(id as usize).and_then(|p| p.take()) {
+            unsafe { drop(Box::from_raw(p.handle)) };
+        }
+    }
+
+    fn can_connect(&self, src: PortId, dst: PortId) -> libc::c_int {
+        let (s, d) = match (self.port(src), self.port(dst)) {
+            (Some(s), Some(d)) => (s, d),
+            _                  => return -1,
+        };
+
+        if !s.is_output() || !d.is_input() || s.port_type != d.port_type {
+            return -1;
+        }
+
+        // jack refuses to connect ports whose owners are not active
+        if !self.owner_active(src) || !self.owner_active(dst) {
+            return -1;
+        }
+
+        if self.connections.contains(&(src, dst)) {
+            return libc::EEXIST;
+        }
+
+        0
+    }
+
+    /// Connects two ports, returning the callbacks to notify (with the lock released)
+    pub(crate) fn connect(&mut self, src: &str, dst: &str) -> Result<Vec<Notify>, libc::c_int> {
+        let (src, dst) = match (self.port_id(src), self.port_id(dst)) {
+            (Some(s), Some(d)) => (s, d),
+            _                  => return Err(-1),
+        };
+
+        let ret = self.can_connect(src, dst);
+        if ret != 0 {
+            return Err(ret);
+        }
+
+        self.connections.push((src, dst));
+        Ok(self.connect_notifications(&[(src, dst)], true))
+    }
+
+    pub(crate) fn disconnect(&mut self, src: &str, dst: &str) -> Result<Vec<Notify>, libc::c_int> {
+        let pair = match (self.port_id(src), self.port_id(dst)) {
+            (Some(s), Some(d)) => (s, d),
+            _                  => return Err(-1),
+        };
+
+        match self.connections.iter().position(|&c| c == pair) {
+            Some(i) => {
+                self.connections.remove(i);
+                Ok(self.connect_notifications(&[pair], false))
+            },
+            None => Err(-1),
+        }
+    }
+
+    /// Removes every connection to the given ports
+    fn disconnect_all(&mut self, ports: &[PortId]) -> Vec<Notify> {
+        let (gone, kept) = self.connections.iter()
+            .partition(|&&(a, b)| ports.contains(&a) || ports.contains(&b));
+
+        self.connections = kept;
+        self.connect_notifications(&gone, false)
+    }
+
+    fn connect_notifications(&self, pairs: &[(PortId, PortId)], connected: bool) -> Vec<Notify> {
+        let mut out = Vec::new();
+        for c in self.active_clients() {
+            if let (Some(f), arg) = c.callbacks.port_connect {
+                for &(a, b) in pairs {
+                    out.push(Notify::PortConnect(f, arg, a, b, connected));
+                }
+            }
+        }
+
+        out
+    }
+
+    fn active_clients(&self) -> Box<dyn Iterator<Item = &ClientState> + '_> {
+        Box::new(self.clients.iter().filter_map(|c| c.as_ref()).filter(|c| c.active))
+    }
+
+    fn ports_of(&self, client: usize) -> Vec<PortId> {
+        (0..self.ports.len() as PortId)
+            .filter(|&id| self.port(id).map_or(false, |p| p.owner == Some(client)))
+            .collect()
+    }
+
+    fn deactivate(&mut self, client: usize) -> Vec<Notify> {
+        match self.clients[client].as_mut() {
+            Some(c) => c.active = false,
+            None    => return Vec::new(),
+        }
+
+        let ports = self.ports_of(client);
+        self.disconnect_all(&ports)
+    }
+
+    /// Fills the given input port's buffer from the ports connected to it
+    fn mix(&mut self, id: PortId) {
+        let sources: Vec<PortId> =
+            self.connections.iter().filter(|c| c.1 == id).map(|c| c.0).collect();
+
+        let nframes = self.buffer_size;
+        let mut audio = vec![0.0; nframes as usize];
+        let mut midi = Vec::new();
+        let mut other = None;
+
+        for &s in &sources {
+            match self.port(s).map(|p| &p.buffer) {
+                Some(&Buffer::Audio(ref b)) => {
+                    for (a, s) in audio.iter_mut().zip(b.iter()) {
+                        *a += *s;
+                    }
+                },
+                Some(&Buffer::Midi(ref b)) => midi.extend(b.events().iter().cloned()),
+                Some(&Buffer::Other(ref b)) => {
+                    if other.is_none() {
+                        other = Some(b.clone());
+                    }
+                },
+                None => (),
+            }
+        }
+
+        // events from different sources are merged in time order
+        midi.sort_by_key(|e| e.0);
+
+        if let Some(p) = self.port_mut(id) {
+            match p.buffer {
+                Buffer::Audio(ref mut b) => b.copy_from_slice(&audio),
+                Buffer::Midi(ref mut b)  => {
+                    b.clear();
+                    for (t, data) in midi {
+                        b.push(t, &data);
+                    }
+                },
+                Buffer::Other(ref mut b) => match other {
+                    Some(o) => b.copy_from_slice(&o),
+                    None    => b.iter_mut().for_each(|x| *x = 0),
+                },
+            }
+        }
+    }
+
+    /// Puts the data queued on the server's output ports into their buffers
+    fn start_cycle(&mut self) {
+        let start = self.frame_time;
+        let nframes = self.buffer_size;
+        self.cycle_start = start;
+
+        for p in self.ports.iter_mut().filter_map(|p| p.as_mut()) {
+            if p.owner.is_some() || !p.is_output() {
+                continue;
+            }
+
+            match p.buffer {
+                Buffer::Audio(ref mut b) => {
+                    for s in b.iter_mut() {
+                        *s = p.audio_in.pop_front().unwrap_or(0.0);
+                    }
+                },
+                Buffer::Midi(ref mut b) => {
+                    b.clear();
+                    let (now, later) = p.midi_in.drain(..)
+                        .partition(|e| e.0.wrapping_sub(start) < nframes);
+
+                    p.midi_in = later;
+                    for (t, data) in now {
+                        b.push(t.wrapping_sub(start), &data);
+                    }
+                },
+                Buffer::Other(_) => (),
+            }
+        }
+    }
+
+    /// Copies whatever reached the server's input ports out of their buffers
+    fn end_cycle(&mut self) {
+        let inputs: Vec<PortId> = (0..self.ports.len() as PortId)
+            .filter(|&id| self.port(id).map_or(false, |p| p.owner.is_none() && p.is_input()))
+            .collect();
+
+        for id in inputs {
+            self.mix(id);
+
+            let start = self.cycle_start;
+            let p = self.port_mut(id).unwrap();
+            match p.buffer {
+                Buffer::Audio(ref b) => p.audio_out.extend_from_slice(b),
+                Buffer::Midi(ref b)  => {
+                    p.midi_out.extend(b.events().iter().map(|e| (start + e.0, e.1.clone())))
+                },
+                Buffer::Other(_) => (),
+            }
+        }
+
+        self.frame_time = self.frame_time.wrapping_add(self.buffer_size);
+    }
+
+    pub(crate) fn queue_audio(&mut self, port: &str, samples: &[DefaultAudioSample]) -> bool {
+        match self.system_port(port, true) {
+            Some(p) => {
+                p.audio_in.extend(samples.iter().cloned());
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub(crate) fn queue_midi(&mut self, port: &str, time: NumFrames, data: &[u8]) -> bool {
+        let at = self.frame_time.wrapping_add(time);
+        match self.system_port(port, true) {
+            Some(p) => {
+                p.midi_in.push((at, data.to_vec()));
+                p.midi_in.sort_by_key(|e| e.0);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub(crate) fn take_audio(&mut self, port: &str) -> Option<Vec<DefaultAudioSample>> {
+        self.system_port(port, false).map(|p| mem::replace(&mut p.audio_out, Vec::new()))
+    }
+
+    pub(crate) fn take_midi(&mut self, port: &str) -> Option<Vec<(NumFrames, Vec<u8>)>> {
+        self.system_port(port, false).map(|p| mem::replace(&mut p.midi_out, Vec::new()))
+    }
+
+    fn system_port(&mut self, name: &str, output: bool) -> Option<&mut PortState> {
+        let id = self.port_id(name)?;
+        self.port_mut(id).filter(|p| p.owner.is_none() && p.is_output() == output)
+    }
+
+    /// Returns the active clients in the order their process callbacks should run: every client
+    /// runs after the clients feeding its inputs, where possible
+    fn process_order(&self) -> Vec<usize> {
+        let active: Vec<usize> = (0..self.clients.len())
+            .filter(|&c| self.clients[c].as_ref().map_or(false, |c| c.active))
+            .collect();
+
+        let edges: Vec<(usize, usize)> = self.connections.iter()
+            .filter_map(|&(a, b)| {
+                match (self.port(a).and_then(|p| p.owner), self.port(b).and_then(|p| p.owner)) {
+                    (Some(x), Some(y)) if x != y => Some((x, y)),
+                    _                            => None,
+                }
+            })
+            .collect();
+
+        let mut order = Vec::with_capacity(active.len());
+        let mut left = active;
+        while !left.is_empty() {
+            let ready = left.iter().position(|&c| {
+                !edges.iter().any(|&(from, to)| to == c && left.contains(&from))
+            });
+
+            // a feedback loop has no valid order, so break it at the oldest client
+            order.push(left.remove(ready.unwrap_or(0)));
+        }
+
+        order
+    }
+
+    /// Removes a client and its ports. The handle is freed by the caller.
+    fn remove_client(&mut self, id: usize) -> Vec<Notify> {
+        let notes = self.deactivate(id);
+        for p in self.ports_of(id) {
+            self.remove_port(p);
+        }
+
+        self.clients[id] = None;
+        notes
+    }
+
+    fn property_notifications(&self, subject: UUID, key: Option<&str>,
+                              change: jack_sys::jack_property_change_t) -> Vec<Notify>
+    {
+        let mut out = Vec::new();
+        for c in self.active_clients() {
+            if let (Some(f), arg) = c.callbacks.property_change {
+                let key = key.map(|k| CString::new(k).unwrap());
+                out.push(Notify::Property(f, arg, subject, key, change));
+            }
+        }
+
+        out
+    }
+
+    pub(crate) fn xrun(&mut self, delay: f32) -> Vec<Notify> {
+        self.xrun_delay = delay;
+        self.active_clients()
+            .filter_map(|c| match c.callbacks.xrun { (Some(f), arg) => Some((f, arg)), _ => None })
+            .map(|(f, arg)| Notify::Xrun(f, arg))
+            .collect()
+    }
+
+    /// Deactivates every client, returning the shutdown callbacks of the ones which were active.
+    /// Like jack, nothing else is said about the connections this breaks.
+    pub(crate) fn shutdown(&mut self) -> Vec<Notify> {
+        let notes = self.active_clients()
+            .filter_map(|c| match c.callbacks.shutdown {
+                (Some(f), arg) => Some(Notify::Shutdown(f, arg)),
+                _              => None,
+            })
+            .collect();
+
+        for id in 0..self.clients.len() {
+            self.deactivate(id);
+        }
+
+        notes
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, rate: NumFrames) -> Vec<Notify> {
+        self.sample_rate = rate;
+        self.active_clients()
+            .filter_map(|c| match c.callbacks.sample_rate {
+                (Some(f), arg) => Some(Notify::SampleRate(f, arg, rate)),
+                _              => None,
+            })
+            .collect()
+    }
+}
+
+/// A callback to run once the graph is unlocked
+pub(crate) enum Notify {
+    PortConnect(unsafe extern "C" fn(PortId, PortId, libc::c_int, Arg), Arg, PortId, PortId, bool),
+    Xrun(unsafe extern "C" fn(Arg) -> libc::c_int, Arg),
+    SampleRate(unsafe extern "C" fn(NumFrames, Arg) -> libc::c_int, Arg, NumFrames),
+    Freewheel(unsafe extern "C" fn(libc::c_int, Arg), Arg, bool),
+    Shutdown(unsafe extern "C" fn(Arg), Arg),
+    Latency(unsafe extern "C" fn(jack_sys::jack_latency_callback_mode_t, Arg), Arg),
+    Property(unsafe extern "C" fn(UUID, *const libc::c_char, jack_sys::jack_property_change_t, Arg),
+             Arg, UUID, Option<CString>, jack_sys::jack_property_change_t),
+}
+
+pub(crate) fn notify(notes: Vec<Notify>) {
+    for n in notes {
+        unsafe {
+            match n {
+                Notify::PortConnect(f, arg, a, b, c) => f(a, b, c as libc::c_int, arg),
+                Notify::Xrun(f, arg)                 => { f(arg); },
+                Notify::SampleRate(f, arg, rate)     => { f(rate, arg); },
+                Notify::Freewheel(f, arg, on)        => f(on as libc::c_int, arg),
+                Notify::Shutdown(f, arg)             => f(arg),
+                Notify::Latency(f, arg)              => {
+                    f(jack_sys::JackCaptureLatency, arg);
+                    f(jack_sys::JackPlaybackLatency, arg);
+                },
+                Notify::Property(f, arg, subject, key, change) => {
+                    f(subject, key.as_ref().map_or(ptr::null(), |k| k.as_ptr()), change, arg)
+                },
+            }
+        }
+    }
+}
+
+/// Runs `n` process cycles
+pub(crate) fn run_cycles(shared: &Shared, n: usize) {
+    for _ in 0..n {
+        let (nframes, order) = {
+            let mut g = lock(shared);
+            g.start_cycle();
+
+            let order = g.process_order();
+            let callbacks: Vec<_> = order.into_iter()
+                .map(|id| {
+                    let c = g.clients[id].as_mut().unwrap();
+                    let init = if c.thread_inited { None } else { c.callbacks.thread_init.0 };
+                    c.thread_inited = true;
+                    (id, init, c.callbacks.thread_init.1, c.callbacks.process)
+                })
+                .collect();
+
+            (g.buffer_size, callbacks)
+        };
+
+        for (id, init, init_arg, process) in order {
+            unsafe {
+                // the first cycle stands in for the start of the client's process thread
+                if let Some(f) = init {
+                    f(init_arg);
+                }
+
+                if let (Some(f), arg) = process {
+                    // jack removes clients whose process callback fails from the graph
+                    if f(nframes, arg) != 0 {
+                        let notes = lock(shared).deactivate(id);
+                        notify(notes);
+                    }
+                }
+            }
+        }
+
+        lock(shared).end_cycle();
+    }
+}
+
+// mallocs a copy of the string, for the functions whose results the caller frees with jack_free
+unsafe fn dup(s: &str) -> *mut libc::c_char {
+    let p = libc::malloc(s.len() + 1) as *mut libc::c_char;
+    ptr::copy_nonoverlapping(s.as_ptr() as *const libc::c_char, p, s.len());
+    *p.offset(s.len() as isize) = 0;
+    p
+}
+
+unsafe fn str_arg<'a>(s: *const libc::c_char) -> &'a str {
+    if s.is_null() { "" } else { CStr::from_ptr(s).to_str().unwrap_or("") }
+}
+
+// ----------------------------------------------------------------------------------------------
+// clients
+
+pub(crate) unsafe fn client_open(
+    name: &CStr,
+    opts: jack_sys::jack_options_t,
+    status: *mut jack_sys::jack_status_t,
+    server: Option<&CStr>)
+    -> *mut jack_client_t
+{
+    let mut st = 0;
+    let ret = open(name, opts, &mut st, server);
+    if !status.is_null() {
+        *status = st;
+    }
+
+    ret
+}
+
+unsafe fn open(
+    name: &CStr,
+    opts: jack_sys::jack_options_t,
+    status: &mut jack_sys::jack_status_t,
+    server: Option<&CStr>)
+    -> *mut jack_client_t
+{
+    let shared = match server {
+        Some(s) => find_server(&s.to_string_lossy()),
+        None    => current(),
+    };
+
+    let shared = match shared {
+        Some(s) => s,
+        None    => {
+            *status = jack_sys::JackFailure | jack_sys::JackServerFailed;
+            return ptr::null_mut();
+        },
+    };
+
+    let requested = name.to_string_lossy().into_owned();
+    if requested.is_empty() || requested.len() >= CLIENT_NAME_SIZE {
+        *status = jack_sys::JackFailure | jack_sys::JackInvalidOption;
+        return ptr::null_mut();
+    }
+
+    let mut g = lock(&shared);
+
+    let mut name = requested.clone();
+    if g.client_by_name(&name).is_some() {
+        if opts & jack_sys::JackUseExactName != 0 {
+            *status = jack_sys::JackFailure | jack_sys::JackNameNotUnique;
+            return ptr::null_mut();
+        }
+
+        let unique = (1..100)
+            .map(|i| format!("{}-{:02}", requested, i))
+            .find(|n| n.len() < CLIENT_NAME_SIZE && g.client_by_name(n).is_none());
+
+        match unique {
+            Some(n) => {
+                name = n;
+                *status |= jack_sys::JackNameNotUnique;
+            },
+            None => {
+                *status = jack_sys::JackFailure | jack_sys::JackNameNotUnique;
+                return ptr::null_mut();
+            },
+        }
+    }
+
+    let id = g.clients.len();
+    let handle = Box::into_raw(Box::new(MockClient {
+        graph: shared.clone(),
+        id:    id,
+        name:  CString::new(name.clone()).unwrap(),
+    }));
+
+    g.clients.push(Some(ClientState {
+        name:          name,
+        uuid:          next_uuid(),
+        active:        false,
+        thread_inited: false,
+        callbacks:     Callbacks::new(),
+    }));
+
+    handle as *mut jack_client_t
+}
+
+pub unsafe fn jack_client_close(c: *mut jack_client_t) -> libc::c_int {
+    let notes = {
+        let cl = client(c);
+        let mut g = lock(&cl.graph);
+        g.remove_client(cl.id)
+    };
+
+    notify(notes);
+    drop(Box::from_raw(c as *mut MockClient));
+    0
+}
+
+pub unsafe fn jack_activate(c: *mut jack_client_t) -> libc::c_int {
+    let cl = client(c);
+    match lock(&cl.graph).clients[cl.id].as_mut() {
+        Some(st) => {
+            st.active = true;
+            0
+        },
+        None => -1,
+    }
+}
+
+pub unsafe fn jack_deactivate(c: *mut jack_client_t) -> libc::c_int {
+    let cl = client(c);
+    let notes = lock(&cl.graph).deactivate(cl.id);
+    notify(notes);
+    0
+}
+
+pub unsafe fn jack_get_client_name(c: *mut jack_client_t) -> *mut libc::c_char {
+    client(c).name.as_ptr() as *mut libc::c_char
+}
+
+pub unsafe fn jack_client_get_uuid(c: *mut jack_client_t) -> *mut libc::c_char {
+    let cl = client(c);
+    let uuid = lock(&cl.graph).clients[cl.id].as_ref().map(|c| c.uuid);
+    uuid.map_or(ptr::null_mut(), |u| dup(&uuid_unparse(u)))
+}
+
+pub unsafe fn jack_get_uuid_for_client_name(c: *mut jack_client_t, name: *const libc::c_char)
+    -> *mut libc::c_char
+{
+    let g = lock(&client(c).graph);
+    g.client_by_name(str_arg(name)).map_or(ptr::null_mut(), |c| dup(&uuid_unparse(c.uuid)))
+}
+
+pub unsafe fn jack_get_client_name_by_uuid(c: *mut jack_client_t, uuid: *const libc::c_char)
+    -> *mut libc::c_char
+{
+    let uuid = match uuid_parse(str_arg(uuid)) {
+        Some(u) => u,
+        None    => return ptr::null_mut(),
+    };
+
+    let g = lock(&client(c).graph);
+    let found = g.clients.iter().filter_map(|c| c.as_ref()).find(|c| c.uuid == uuid);
+    found.map_or(ptr::null_mut(), |c| dup(&c.name))
+}
+
+pub unsafe fn jack_get_sample_rate(c: *mut jack_client_t) -> NumFrames {
+    lock(&client(c).graph).sample_rate
+}
+
+pub unsafe fn jack_get_buffer_size(c: *mut jack_client_t) -> NumFrames {
+    lock(&client(c).graph).buffer_size
+}
+
+pub unsafe fn jack_get_xrun_delayed_usecs(c: *mut jack_client_t) -> libc::c_float {
+    lock(&client(c).graph).xrun_delay
+}
+
+pub unsafe fn jack_frames_since_cycle_start(_c: *const jack_client_t) -> NumFrames {
+    // time does not pass during a mock cycle
+    0
+}
+
+pub unsafe fn jack_last_frame_time(c: *const jack_client_t) -> NumFrames {
+    lock(&client(c).graph).cycle_start
+}
+
+pub unsafe fn jack_set_freewheel(c: *mut jack_client_t, onoff: libc::c_int) -> libc::c_int {
+    let on = onoff != 0;
+    let notes: Vec<Notify> = {
+        let mut g = lock(&client(c).graph);
+        if g.freewheel == on {
+            return 0;
+        }
+
+        g.freewheel = on;
+        g.active_clients()
+            .filter_map(|c| match c.callbacks.freewheel {
+                (Some(f), arg) => Some(Notify::Freewheel(f, arg, on)),
+                _              => None,
+            })
+            .collect()
+    };
+
+    notify(notes);
+    0
+}
+
+pub unsafe fn jack_recompute_total_latencies(c: *mut jack_client_t) -> libc::c_int {
+    let notes: Vec<Notify> = {
+        let g = lock(&client(c).graph);
+        g.active_clients()
+            .filter_map(|c| match c.callbacks.latency {
+                (Some(f), arg) => Some(Notify::Latency(f, arg)),
+                _              => None,
+            })
+            .collect()
+    };
+
+    notify(notes);
+    0
+}
+
+// ----------------------------------------------------------------------------------------------
+// callbacks. Like jack, these can only be set while the client is inactive
+
+unsafe fn set_callback<F>(c: *mut jack_client_t, f: F) -> libc::c_int
+    where F: FnOnce(&mut Callbacks)
+{
+    let cl = client(c);
+    let mut g = lock(&cl.graph);
+    match g.clients[cl.id].as_mut() {
+        Some(ref mut st) if !st.active => {
+            f(&mut st.callbacks);
+            0
+        },
+        _ => -1,
+    }
+}
+
+pub unsafe fn jack_set_process_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackProcessCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.process = (cb, arg))
+}
+
+pub unsafe fn jack_set_thread_init_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackThreadInitCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.thread_init = (cb, arg))
+}
+
+pub unsafe fn jack_set_sample_rate_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackSampleRateCallback, arg: Arg) -> libc::c_int
+{
+    let ret = set_callback(c, |cbs| cbs.sample_rate = (cb, arg));
+
+    // jack calls the callback with the current rate as soon as it is set
+    if let (0, Some(f)) = (ret, cb) {
+        f(jack_get_sample_rate(c), arg);
+    }
+
+    ret
+}
+
+pub unsafe fn jack_set_port_connect_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackPortConnectCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.port_connect = (cb, arg))
+}
+
+pub unsafe fn jack_set_xrun_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackXRunCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.xrun = (cb, arg))
+}
+
+pub unsafe fn jack_set_freewheel_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackFreewheelCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.freewheel = (cb, arg))
+}
+
+pub unsafe fn jack_on_shutdown(
+    c: *mut jack_client_t, cb: jack_sys::JackShutdownCallback, arg: Arg)
+{
+    set_callback(c, |cbs| cbs.shutdown = (cb, arg));
+}
+
+pub unsafe fn jack_set_latency_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackLatencyCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.latency = (cb, arg))
+}
+
+pub unsafe fn jack_set_property_change_callback(
+    c: *mut jack_client_t, cb: jack_sys::JackPropertyChangeCallback, arg: Arg) -> libc::c_int
+{
+    set_callback(c, |cbs| cbs.property_change = (cb, arg))
+}
+
+pub unsafe fn jack_set_process_thread(
+    _c: *mut jack_client_t, _cb: jack_sys::JackThreadCallback, _arg: Arg) -> libc::c_int
+{
+    // a process thread would never return control to run_cycles
+    -1
+}
+
+pub unsafe fn jack_cycle_wait(c: *mut jack_client_t) -> NumFrames {
+    jack_get_buffer_size(c)
+}
+
+pub unsafe fn jack_cycle_signal(_c: *mut jack_client_t, _status: libc::c_int) {}
+
+// ----------------------------------------------------------------------------------------------
+// threads. The mock server never runs realtime
+
+pub unsafe fn jack_is_realtime(_c: *mut jack_client_t) -> libc::c_int {
+    0
+}
+
+pub unsafe fn jack_client_real_time_priority(_c: *mut jack_client_t) -> libc::c_int {
+    -1
+}
+
+pub unsafe fn jack_client_max_real_time_priority(_c: *mut jack_client_t) -> libc::c_int {
+    -1
+}
+
+pub unsafe fn jack_acquire_real_time_scheduling(
+    _thread: jack_sys::jack_native_thread_t, _priority: libc::c_int) -> libc::c_int
+{
+    libc::EPERM
+}
+
+pub unsafe fn jack_drop_real_time_scheduling(_thread: jack_sys::jack_native_thread_t)
+    -> libc::c_int
+{
+    0
+}
+
+pub unsafe fn jack_client_create_thread(
+    _c: *mut jack_client_t,
+    thread: *mut jack_sys::jack_native_thread_t,
+    _priority: libc::c_int,
+    _realtime: libc::c_int,
+    start_routine: Option<unsafe extern "C" fn(Arg) -> Arg>,
+    arg: Arg)
+    -> libc::c_int
+{
+    let f = match start_routine {
+        Some(f) => f,
+        None    => return libc::EINVAL,
+    };
+
+    // pthread_create's signature only differs in not being marked unsafe
+    let f: extern "C" fn(Arg) -> Arg = mem::transmute(f);
+    libc::pthread_create(thread, ptr::null(), f, arg)
+}
+
+// ----------------------------------------------------------------------------------------------
+// internal clients are not supported
+
+pub(crate) unsafe fn internal_client_load(
+    _c: *mut jack_client_t,
+    _name: &CStr,
+    _opts: jack_sys::jack_options_t,
+    status: *mut jack_sys::jack_status_t,
+    _load_name: Option<&CStr>,
+    _load_init: Option<&CStr>)
+    -> jack_sys::jack_intclient_t
+{
+    *status = jack_sys::JackFailure | jack_sys::JackLoadFailure;
+    0
+}
+
+pub unsafe fn jack_internal_client_handle(
+    _c: *mut jack_client_t, _name: *const libc::c_char, status: *mut jack_sys::jack_status_t)
+    -> jack_sys::jack_intclient_t
+{
+    *status = jack_sys::JackFailure | jack_sys::JackNoSuchClient;
+    0
+}
+
+pub unsafe fn jack_get_internal_client_name(
+    _c: *mut jack_client_t, _handle: jack_sys::jack_intclient_t) -> *mut libc::c_char
+{
+    ptr::null_mut()
+}
+
+pub unsafe fn jack_internal_client_unload(
+    _c: *mut jack_client_t, _handle: jack_sys::jack_intclient_t) -> jack_sys::jack_status_t
+{
+    jack_sys::JackFailure | jack_sys::JackNoSuchClient
+}
+
+// ----------------------------------------------------------------------------------------------
+// ports
+
+pub unsafe fn jack_port_register(
+    c: *mut jack_client_t,
+    name: *const libc::c_char,
+    port_type: *const libc::c_char,
+    flags: libc::c_ulong,
+    buffer_size: libc::c_ulong)
+    -> *mut jack_port_t
+{
+    let cl = client(c);
+    let mut g = lock(&cl.graph);
+
+    let full = format!("{}:{}", cl.name.to_string_lossy(), str_arg(name));
+    let id = g.add_port(&cl.graph, full, str_arg(port_type), flags, Some(cl.id),
+                        buffer_size as usize);
+
+    id.map_or(ptr::null_mut(), |id| g.port(id).unwrap().handle as *mut jack_port_t)
+}
+
+pub unsafe fn jack_port_unregister(c: *mut jack_client_t, p: *mut jack_port_t) -> libc::c_int {
+    let cl = client(c);
+    let id = port(p).id;
+
+    let notes = {
+        let mut g = lock(&cl.graph);
+        if g.port(id).and_then(|p| p.owner) != Some(cl.id) {
+            return -1;
+        }
+
+        let notes = g.disconnect_all(&[id]);
+        g.remove_port(id);
+        notes
+    };
+
+    notify(notes);
+    0
+}
+
+pub unsafe fn jack_port_by_name(c: *mut jack_client_t, name: *const libc::c_char)
+    -> *mut jack_port_t
+{
+    let g = lock(&client(c).graph);
+    let id = g.ports.iter().position(|p| p.as_ref().map_or(false, |p| p.name() == str_arg(name)));
+    id.map_or(ptr::null_mut(), |id| g.ports[id].as_ref().unwrap().handle as *mut jack_port_t)
+}
+
+pub unsafe fn jack_port_by_id(c: *mut jack_client_t, id: PortId) -> *mut jack_port_t {
+    let g = lock(&client(c).graph);
+    g.port(id).map_or(ptr::null_mut(), |p| p.handle as *mut jack_port_t)
+}
+
+pub unsafe fn jack_get_ports(
+    c: *mut jack_client_t,
+    name_pattern: *const libc::c_char,
+    type_pattern: *const libc::c_char,
+    flags: libc::c_ulong)
+    -> *mut *const libc::c_char
+{
+    let g = lock(&client(c).graph);
+
+    // jack matches the patterns as regular expressions, the mock only looks for substrings
+    let names: Vec<&str> = g.ports.iter()
+        .filter_map(|p| p.as_ref())
+        .filter(|p| p.name().contains(str_arg(name_pattern)))
+        .filter(|p| p.port_type.contains(str_arg(type_pattern)))
+        .filter(|p| p.flags & flags == flags)
+        .map(|p| p.name())
+        .collect();
+
+    if names.is_empty() {
+        return ptr::null_mut();
+    }
+
+    // one block holding the null terminated pointer array followed by the strings, so that a
+    // single jack_free releases everything (as it does for jack's own result)
+    let table = (names.len() + 1) * mem::size_of::<*const libc::c_char>();
+    let total = table + names.iter().map(|n| n.len() + 1).sum::<usize>();
+    let block = libc::malloc(total) as *mut u8;
+
+    let ptrs = block as *mut *const libc::c_char;
+    let mut text = block.offset(table as isize);
+    for (i, n) in names.iter().enumerate() {
+        ptr::copy_nonoverlapping(n.as_ptr(), text, n.len());
+        *text.offset(n.len() as isize) = 0;
+        *ptrs.offset(i as isize) = text as *const libc::c_char;
+        text = text.offset(n.len() as isize + 1);
+    }
+
+    *ptrs.offset(names.len() as isize) = ptr::null();
+    ptrs
+}
+
+pub unsafe fn jack_port_name(p: *const jack_port_t) -> *const libc::c_char {
+    let mp = port(p);
+    let shared = mp.graph();
+    let g = lock(&shared);
+
+    // the name lives in the heap buffer of the CString, which stays put until the port is removed
+    g.port(mp.id).map_or(ptr::null(), |p| p.name.as_ptr())
+}
+
+pub unsafe fn jack_port_name_size() -> libc::c_int {
+    PORT_NAME_SIZE as libc::c_int
+}
+
+pub unsafe fn jack_port_uuid(p: *const jack_port_t) -> UUID {
+    let mp = port(p);
+    let shared = mp.graph();
+    let g = lock(&shared);
+    g.port(mp.id).map_or(0, |p| p.uuid)
+}
+
+pub unsafe fn jack_port_flags(p: *const jack_port_t) -> libc::c_int {
+    let mp = port(p);
+    let shared = mp.graph();
+    let g = lock(&shared);
+    g.port(mp.id).map_or(0, |p| p.flags as libc::c_int)
+}
+
+pub unsafe fn jack_port_type_get_buffer_size(c: *mut jack_client_t, ptype: *const libc::c_char)
+    -> libc::size_t
+{
+    let ptype = str_arg(ptype);
+    if ptype == port_type::DEFAULT_AUDIO_TYPE || ptype == port_type::DEFAULT_MIDI_TYPE {
+        jack_get_buffer_size(c) as usize * mem::size_of::<DefaultAudioSample>()
+    } else {
+        0
+    }
+}
+
+pub unsafe fn jack_port_get_aliases(p: *const jack_port_t, aliases: *mut *mut libc::c_char)
+    -> libc::c_int
+{
+    let mp = port(p);
+    let shared = mp.graph();
+    let g = lock(&shared);
+    let st = match g.port(mp.id) {
+        Some(st) => st,
+        None     => return -1,
+    };
+
+    for (i, a) in st.aliases.iter().enumerate() {
+        let dst = *aliases.offset(i as isize);
+        ptr::copy_nonoverlapping(a.as_ptr() as *const libc::c_char, dst, a.len());
+        *dst.offset(a.len() as isize) = 0;
+    }
+
+    st.aliases.len() as libc::c_int
+}
+
+pub unsafe fn jack_port_set_alias(p: *mut jack_port_t, alias: *const libc::c_char)
+    -> libc::c_int
+{
+    let mp = port(p);
+    let shared = mp.graph();
+    let mut g = lock(&shared);
+    let alias = str_arg(alias);
+
+    match g.port_mut(mp.id) {
+        Some(st) if st.aliases.len() < 2 && alias.len() < PORT_NAME_SIZE => {
+            st.aliases.push(alias.to_string());
+            0
+        },
+        _ => -1,
+    }
+}
+
+pub unsafe fn jack_port_unset_alias(p: *mut jack_port_t, alias: *const libc::c_char)
+    -> libc::c_int
+{
+    let mp = port(p);
+    let shared = mp.graph();
+    let mut g = lock(&shared);
+    let alias = str_arg(alias);
+
+    match g.port_mut(mp.id) {
+        Some(st) => match st.aliases.iter().position(|a| a == alias) {
+            Some(i) => {
+                st.aliases.remove(i);
+                0
+            },
+            None => -1,
+        },
+        None => -1,
+    }
+}
+
+pub unsafe fn jack_port_get_latency_range(
+    p: *mut jack_port_t,
+    mode: jack_sys::jack_latency_callback_mode_t,
+    range: *mut jack_sys::jack_latency_range_t)
+{
+    let mp = port(p);
+    let shared = mp.graph();
+    let g = lock(&shared);
+    if let Some(st) = g.port(mp.id) {
+        let (min, max) = st.latency[(mode == jack_sys::JackPlaybackLatency) as usize];
+        *range = jack_sys::jack_latency_range_t { min: min, max: max };
+    }
+}
+
+pub unsafe fn jack_port_set_latency_range(
+    p: *mut jack_port_t,
+    mode: jack_sys::jack_latency_callback_mode_t,
+    range: *mut jack_sys::jack_latency_range_t)
+{
+    let mp = port(p);
+    let shared = mp.graph();
+    let mut g = lock(&shared);
+    if let Some(st) = g.port_mut(mp.id) {
+        let r = *range;
+        st.latency[(mode == jack_sys::JackPlaybackLatency) as usize] = (r.min, r.max);
+    }
+}
+
+pub unsafe fn jack_port_get_buffer(p: *mut jack_port_t, _nframes: NumFrames) -> Arg {
+    let mp = port(p);
+    let shared = mp.graph();
+    let mut g = lock(&shared);
+
+    if g.port(mp.id).map_or(false, |st| st.is_input()) {
+        g.mix(mp.id);
+    }
+
+    // the buffers are never resized, so the pointers stay valid until the port is removed
+    match g.port_mut(mp.id).map(|st| &mut st.buffer) {
+        Some(&mut Buffer::Audio(ref mut b)) => b.as_mut_ptr() as Arg,
+        Some(&mut Buffer::Midi(ref mut b))  => &mut **b as *mut MidiBuffer as Arg,
+        Some(&mut Buffer::Other(ref mut b)) => b.as_mut_ptr() as Arg,
+        None                                => ptr::null_mut(),
+    }
+}
+
+pub unsafe fn jack_connect(
+    c: *mut jack_client_t, src: *const libc::c_char, dst: *const libc::c_char) -> libc::c_int
+{
+    let res = lock(&client(c).graph).connect(str_arg(src), str_arg(dst));
+    match res {
+        Ok(notes) => {
+            notify(notes);
+            0
+        },
+        Err(e) => e,
+    }
+}
+
+pub unsafe fn jack_disconnect(
+    c: *mut jack_client_t, src: *const libc::c_char, dst: *const libc::c_char) -> libc::c_int
+{
+    let res = lock(&client(c).graph).disconnect(str_arg(src), str_arg(dst));
+    match res {
+        Ok(notes) => {
+            notify(notes);
+            0
+        },
+        Err(e) => e,
+    }
+}
+
+// ----------------------------------------------------------------------------------------------
+// midi. A midi port buffer pointer points at a MidiBuffer
+
+unsafe fn midi<'a>(buf: Arg) -> &'a mut MidiBuffer {
+    &mut *(buf as *mut MidiBuffer)
+}
+
+pub unsafe fn jack_midi_clear_buffer(buf: Arg) {
+    midi(buf).clear()
+}
+
+pub unsafe fn jack_midi_get_event_count(buf: Arg) -> u32 {
+    midi(buf).events().len() as u32
+}
+
+pub unsafe fn jack_midi_get_lost_event_count(buf: Arg) -> u32 {
+    midi(buf).lost()
+}
+
+pub unsafe fn jack_midi_max_event_size(buf: Arg) -> libc::size_t {
+    midi(buf).max_event_size()
+}
+
+pub unsafe fn jack_midi_event_get(event: *mut jack_sys::jack_midi_event_t, buf: Arg, index: u32)
+    -> libc::c_int
+{
+    match midi(buf).events().get(index as usize) {
+        Some(&(time, ref data)) => {
+            *event = jack_sys::jack_midi_event_t {
+                time:   time,
+                size:   data.len(),
+                buffer: data.as_ptr() as *mut jack_sys::jack_midi_data_t,
+            };
+
+            0
+        },
+        None => -libc::ENODATA,
+    }
+}
+
+pub unsafe fn jack_midi_event_write(
+    buf: Arg, time: NumFrames, data: *const jack_sys::jack_midi_data_t, size: libc::size_t)
+    -> libc::c_int
+{
+    midi(buf).push(time, ::std::slice::from_raw_parts(data, size))
+}
+
+// ----------------------------------------------------------------------------------------------
+// messages. The mock never prints any, so there is nothing to route
+
+pub unsafe fn jack_set_error_function(_func: Option<unsafe extern "C" fn(*const libc::c_char)>) {}
+
+pub unsafe fn jack_set_info_function(_func: Option<unsafe extern "C" fn(*const libc::c_char)>) {}
+
+// ----------------------------------------------------------------------------------------------
+// metadata. Properties belong to a server; the lookups which are not given a client use the
+// thread's current server
+
+pub unsafe fn jack_free(p: Arg) {
+    libc::free(p)
+}
+
+pub unsafe fn jack_set_property(
+    c: *mut jack_client_t,
+    subject: UUID,
+    key: *const libc::c_char,
+    value: *const libc::c_char,
+    ptype: *const libc::c_char)
+    -> libc::c_int
+{
+    let key = str_arg(key);
+    let ptype = if ptype.is_null() { None } else { Some(str_arg(ptype).to_string()) };
+
+    let notes = {
+        let mut g = lock(&client(c).graph);
+        let change = match g.properties.iter_mut().find(|p| p.subject == subject && p.key == key) {
+            Some(p) => {
+                p.value = str_arg(value).to_string();
+                p.prop_type = ptype;
+                jack_sys::PropertyChanged
+            },
+            None => {
+                g.properties.push(PropertyState {
+                    subject:   subject,
+                    key:       key.to_string(),
+                    value:     str_arg(value).to_string(),
+                    prop_type: ptype,
+                });
+
+                jack_sys::PropertyCreated
+            },
+        };
+
+        g.property_notifications(subject, Some(key), change)
+    };
+
+    notify(notes);
+    0
+}
+
+pub unsafe fn jack_get_property(
+    subject: UUID,
+    key: *const libc::c_char,
+    value: *mut *mut libc::c_char,
+    ptype: *mut *mut libc::c_char)
+    -> libc::c_int
+{
+    let shared = match current() {
+        Some(s) => s,
+        None    => return -1,
+    };
+
+    let g = lock(&shared);
+    match g.properties.iter().find(|p| p.subject == subject && p.key == str_arg(key)) {
+        Some(p) => {
+            *value = dup(&p.value);
+            *ptype = p.prop_type.as_ref().map_or(ptr::null_mut(), |t| dup(t));
+            0
+        },
+        None => -1,
+    }
+}
+
+// fills in a description of the subject, with everything allocated the way jack_free_description
+// expects
+// the malloc'ed memory handed out here is uninitialised, so it is only ever written through raw
+// pointers, never through a reference
+unsafe fn describe(g: &Graph, subject: UUID) -> jack_description_t {
+    let props: Vec<&PropertyState> = g.properties.iter().filter(|p| p.subject == subject).collect();
+
+    let array = libc::malloc(props.len().max(1) * mem::size_of::<jack_property_t>())
+        as *mut jack_property_t;
+
+    for (i, p) in props.iter().enumerate() {
+        ptr::write(array.offset(i as isize), jack_property_t {
+            key:   dup(&p.key),
+            data:  dup(&p.value),
+            _type: p.prop_type.as_ref().map_or(ptr::null_mut(), |t| dup(t)),
+        });
+    }
+
+    jack_description_t {
+        subject:       subject,
+        property_cnt:  props.len() as u32,
+        properties:    array,
+        property_size: props.len() as u32,
+    }
+}
+
+pub unsafe fn jack_get_properties(subject: UUID, desc: *mut jack_description_t) -> libc::c_int {
+    match current() {
+        Some(s) => {
+            let d = describe(&lock(&s), subject);
+            let cnt = d.property_cnt;
+            ptr::write(desc, d);
+            cnt as libc::c_int
+        },
+        None    => -1,
+    }
+}
+
+pub unsafe fn jack_get_all_properties(descs: *mut *mut jack_description_t) -> libc::c_int {
+    let shared = match current() {
+        Some(s) => s,
+        None    => return -1,
+    };
+
+    let g = lock(&shared);
+    let mut subjects: Vec<UUID> = g.properties.iter().map(|p| p.subject).collect();
+    subjects.sort();
+    subjects.dedup();
+
+    let array = libc::malloc(subjects.len().max(1) * mem::size_of::<jack_description_t>())
+        as *mut jack_description_t;
+
+    for (i, &s) in subjects.iter().enumerate() {
+        ptr::write(array.offset(i as isize), describe(&g, s));
+    }
+
+    *descs = array;
+    subjects.len() as libc::c_int
+}
+
+pub unsafe fn jack_free_description(desc: *mut jack_description_t, free_itself: libc::c_int) {
+    let d = &mut *desc;
+    for i in 0..d.property_cnt as isize {
+        let p = &*d.properties.offset(i);
+        libc::free(p.key as Arg);
+        libc::free(p.data as Arg);
+        libc::free(p._type as Arg);
+    }
+
+    libc::free(d.properties as Arg);
+    if free_itself != 0 {
+        libc::free(desc as Arg);
+    }
+}
+
+pub unsafe fn jack_remove_property(c: *mut jack_client_t, subject: UUID, key: *const libc::c_char)
+    -> libc::c_int
+{
+    let key = str_arg(key);
+    let notes = {
+        let mut g = lock(&client(c).graph);
+        match g.properties.iter().position(|p| p.subject == subject && p.key == key) {
+            Some(i) => {
+                g.properties.remove(i);
+                g.property_notifications(subject, Some(key), jack_sys::PropertyDeleted)
+            },
+            None => return -1,
+        }
+    };
+
+    notify(notes);
+    0
+}
+
+pub unsafe fn jack_remove_properties(c: *mut jack_client_t, subject: UUID) -> libc::c_int {
+    let (count, notes) = {
+        let mut g = lock(&client(c).graph);
+        let before = g.properties.len();
+        g.properties.retain(|p| p.subject != subject);
+
+        let count = before - g.properties.len();
+        (count, g.property_notifications(subject, None, jack_sys::PropertyDeleted))
+    };
+
+    notify(notes);
+    count as libc::c_int
+}
+
+pub unsafe fn jack_remove_all_properties(c: *mut jack_client_t) -> libc::c_int {
+    let notes = {
+        let mut g = lock(&client(c).graph);
+        let mut subjects: Vec<UUID> = g.properties.drain(..).map(|p| p.subject).collect();
+        subjects.sort();
+        subjects.dedup();
+
+        subjects.into_iter()
+            .flat_map(|s| g.property_notifications(s, None, jack_sys::PropertyDeleted))
+            .collect()
+    };
+
+    notify(notes);
+    0
+}
@@ -0,0 +1,454 @@
+use jack_sys;
+use libc;
+
+use callbackhandler::*;
+use midi::*;
+use port::*;
+use types::*;
+
+/// Runs a `ProcessHandler` without JACK, feeding it from in-memory buffers and collecting what it
+/// writes. Meant for tests which compare a handler's output against known good data.
+///
+/// The host hands out port handles in place of the ones a `Client` would register. Build the
+/// handler with those, then `run` it: each cycle gets a fresh `CallbackContext`, the inputs are
+/// sliced into `nframes` sized blocks (padded with silence once the data runs out), and the
+/// outputs are appended to the host's buffers, exactly as the handler would see in a real cycle.
+///
+/// The handles only work for getting buffers during `run`. Passing them to anything which talks
+/// to JACK (`get_name`, `Client::unregister_port`, a real process cycle, ...) panics.
+///
+/// ```ignore
+/// let mut host = OfflineHost::new(64);
+/// let input = host.audio_input(&samples);
+/// let output = host.audio_output();
+///
+/// let mut handler = MyFilter::new(input, output);
+/// host.run_to_end(&mut handler).unwrap();
+/// assert_eq!(host.audio_output_data(&output), &expected[..]);
+/// ```
+pub struct OfflineHost {
+    nframes: NumFrames,
+    cycles:  usize,
+
+    // boxed so the pointer in the CallbackContext does not depend on where the host lives
+    ports: Box<HostPorts>,
+}
+
+impl OfflineHost {
+    /// Creates a host which runs cycles of `nframes` frames
+    pub fn new(nframes: NumFrames) -> Self {
+        assert!(nframes > 0, "nframes must be positive");
+
+        OfflineHost {
+            nframes: nframes,
+            cycles:  0,
+            ports:   Box::new(HostPorts { ports: Vec::new(), buffers: Vec::new() }),
+        }
+    }
+
+    /// Adds an audio input which plays `samples`, followed by silence
+    pub fn audio_input(&mut self, samples: &[DefaultAudioSample])
+        -> InputPortHandle<DefaultAudioSample>
+    {
+        let buffer = vec![0.0; self.nframes as usize];
+        InputPortHandle::new(self.ports.add(HostPort::AudioIn(samples.to_vec(), buffer)))
+    }
+
+    /// Adds an audio output, which collects everything written to it
+    pub fn audio_output(&mut self) -> OutputPortHandle<DefaultAudioSample> {
+        let buffer = vec![0.0; self.nframes as usize];
+        OutputPortHandle::new(self.ports.add(HostPort::AudioOut(buffer, Vec::new())))
+    }
+
+    /// Adds a midi input which delivers `events`. Each event is given as the frame (counted from
+    /// the start of the first cycle) it arrives at, and its bytes.
+    pub fn midi_input(&mut self, events: &[(NumFrames, Vec<u8>)]) -> InputPortHandle<MidiEvent> {
+        let mut events = events.to_vec();
+        events.sort_by_key(|e| e.0);
+
+        let buffer = MidiBuffer::new(self.nframes);
+        InputPortHandle::new(self.ports.add(HostPort::MidiIn(events, buffer)))
+    }
+
+    /// Adds a midi output, which collects every event written to it
+    pub fn midi_output(&mut self) -> OutputPortHandle<MidiEvent> {
+        let buffer = MidiBuffer::new(self.nframes);
+        OutputPortHandle::new(self.ports.add(HostPort::MidiOut(buffer, Vec::new())))
+    }
+
+    /// Runs `cycles` process cycles. Stops early, returning the handler's return value, if the
+    /// handler returns nonzero (JACK would remove the client from the graph).
+    pub fn run<H>(&mut self, handler: &mut H, cycles: usize) -> Result<(), i32>
+        where H: ProcessHandler + ?Sized
+    {
+        for _ in 0..cycles {
+            let start = self.frames_processed() as NumFrames;
+            self.ports.start_cycle(start, self.nframes);
+
+            let ret = {
+                let ctx = CallbackContext::offline(&mut *self.ports);
+                handler.process(&ctx, self.nframes)
+            };
+
+            self.ports.end_cycle(start);
+            self.cycles += 1;
+
+            if ret != 0 {
+                return Err(ret);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs cycles until every input has been played out completely
+    pub fn run_to_end<H>(&mut self, handler: &mut H) -> Result<(), i32>
+        where H: ProcessHandler + ?Sized
+    {
+        let end = self.ports.input_len();
+        let done = self.frames_processed();
+        let nframes = self.nframes as usize;
+
+        let cycles = end.saturating_sub(done).div_ceil(nframes);
+        self.run(handler, cycles)
+    }
+
+    /// The number of frames run so far
+    pub fn frames_processed(&self) -> usize {
+        self.cycles * self.nframes as usize
+    }
+
+    /// Everything written to an output created with `audio_output`
+    pub fn audio_output_data(&self, port: &OutputPortHandle<DefaultAudioSample>)
+        -> &[DefaultAudioSample]
+    {
+        match *self.ports.get(unsafe { port.get_raw() }) {
+            HostPort::AudioOut(_, ref data) => data,
+            _                               => panic!("not an audio output port"),
+        }
+    }
+
+    /// Every event written to an output created with `midi_output`, with the frame (counted from
+    /// the start of the first cycle) it was written at
+    pub fn midi_output_data(&self, port: &OutputPortHandle<MidiEvent>) -> &[(NumFrames, Vec<u8>)] {
+        match *self.ports.get(unsafe { port.get_raw() }) {
+            HostPort::MidiOut(_, ref events) => events,
+            _                                => panic!("not a midi output port"),
+        }
+    }
+}
+
+/// Whether the port handle was given out by an `OfflineHost`. Those handles hold the port's index,
+/// shifted up with the low bit set, which can never be the address of a real `jack_port_t`. They
+/// must be kept away from libjack.
+pub(crate) fn is_host_port(port: *mut jack_sys::jack_port_t) -> bool {
+    port as usize & 1 == 1
+}
+
+// a host port's data and its buffer for the current cycle
+enum HostPort {
+    AudioIn(Vec<DefaultAudioSample>, Vec<DefaultAudioSample>),
+    AudioOut(Vec<DefaultAudioSample>, Vec<DefaultAudioSample>),
+    MidiIn(Vec<(NumFrames, Vec<u8>)>, MidiBuffer),
+    MidiOut(MidiBuffer, Vec<(NumFrames, Vec<u8>)>),
+}
+
+// where a port's buffer is for the current cycle
+#[derive(Clone, Copy)]
+enum HostBuffer {
+    Audio(*mut DefaultAudioSample),
+    Midi(*mut MidiBuffer),
+}
+
+/// The ports of an `OfflineHost`, which a `CallbackContext` looks buffers up in during an offline
+/// cycle. The port handles the host gives out hold the port's index instead of a pointer, see
+/// `is_host_port`.
+pub(crate) struct HostPorts {
+    ports: Vec<HostPort>,
+
+    // the buffers of every port, found once at the start of each cycle. The handler may hold on to
+    // any number of them at once, so they are never looked up through `ports` during the cycle
+    buffers: Vec<HostBuffer>,
+}
+
+impl HostPorts {
+    fn add(&mut self, port: HostPort) -> *mut jack_sys::jack_port_t {
+        self.ports.push(port);
+        ((self.ports.len() - 1) << 1 | 1) as *mut jack_sys::jack_port_t
+    }
+
+    fn index(&self, port: *mut jack_sys::jack_port_t) -> usize {
+        let index = port as usize >> 1;
+        if !is_host_port(port) || index >= self.ports.len() {
+            panic!("port does not belong to this OfflineHost");
+        }
+
+        index
+    }
+
+    fn get(&self, port: *mut jack_sys::jack_port_t) -> &HostPort {
+        &self.ports[self.index(port)]
+    }
+
+    /// The port's buffer for this cycle, as `jack_port_get_buffer` would return it
+    pub(crate) fn buffer(&self, port: *mut jack_sys::jack_port_t) -> *mut libc::c_void {
+        match self.buffers[self.index(port)] {
+            HostBuffer::Audio(b) => b as *mut libc::c_void,
+            HostBuffer::Midi(b)  => b as *mut libc::c_void,
+        }
+    }
+
+    pub(crate) fn midi(&self, port: *mut jack_sys::jack_port_t) -> *mut MidiBuffer {
+        match self.buffers[self.index(port)] {
+            HostBuffer::Midi(b) => b,
+            _                   => panic!("not a midi port"),
+        }
+    }
+
+    // the frame after the last input sample or event
+    fn input_len(&self) -> usize {
+        self.ports.iter()
+            .map(|p| match *p {
+                HostPort::AudioIn(ref data, _)  => data.len(),
+                HostPort::MidiIn(ref events, _) => events.last().map_or(0, |e| e.0 as usize + 1),
+                _                               => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn start_cycle(&mut self, start: NumFrames, nframes: NumFrames) {
+        for p in self.ports.iter_mut() {
+            match *p {
+                HostPort::AudioIn(ref data, ref mut b) => {
+                    let from = (start as usize).min(data.len());
+                    let to = (from + nframes as usize).min(data.len());
+
+                    let n = to - from;
+                    b[..n].copy_from_slice(&data[from..to]);
+                    for s in b[n..].iter_mut() {
+                        *s = 0.0;
+                    }
+                },
+
+                // jack does not clear output buffers, but a known starting point keeps the
+                // results reproducible
+                HostPort::AudioOut(ref mut b, _) => {
+                    for s in b.iter_mut() {
+                        *s = 0.0;
+                    }
+                },
+
+                HostPort::MidiIn(ref events, ref mut b) => {
+                    b.clear();
+                    let now = events.iter().filter(|e| e.0 >= start && e.0 - start < nframes);
+                    for &(time, ref data) in now {
+                        // events which do not fit are dropped, as they would be by jack
+                        b.push(time - start, data);
+                    }
+                },
+
+                HostPort::MidiOut(ref mut b, _) => b.clear(),
+            }
+        }
+
+        self.buffers = self.ports.iter_mut()
+            .map(|p| match *p {
+                HostPort::AudioIn(_, ref mut b) | HostPort::AudioOut(ref mut b, _) => {
+                    HostBuffer::Audio(b.as_mut_ptr())
+                },
+                HostPort::MidiIn(_, ref mut b) | HostPort::MidiOut(ref mut b, _) => {
+                    HostBuffer::Midi(b)
+                },
+            })
+            .collect();
+    }
+
+    fn end_cycle(&mut self, start: NumFrames) {
+        for p in self.ports.iter_mut() {
+            match *p {
+                HostPort::AudioOut(ref b, ref mut data) => data.extend_from_slice(b),
+                HostPort::MidiOut(ref b, ref mut events) => {
+                    events.extend(b.events().iter().map(|e| (start + e.0, e.1.clone())))
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Gain {
+        input:  InputPortHandle<DefaultAudioSample>,
+        output: OutputPortHandle<DefaultAudioSample>,
+    }
+
+    impl ProcessHandler for Gain {
+        fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+            let i = self.input.get_read_buffer(nframes, ctx);
+            let o = self.output.get_write_buffer(nframes, ctx);
+            for (o, i) in o.iter_mut().zip(i.iter()) {
+                *o = *i * 2.0;
+            }
+
+            0
+        }
+    }
+
+    #[test]
+    fn audio_in_chunks() {
+        let mut host = OfflineHost::new(4);
+        let input = host.audio_input(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let output = host.audio_output();
+
+        let mut gain = Gain { input: input, output: output };
+        host.run_to_end(&mut gain).unwrap();
+
+        // the last cycle is padded with silence
+        let expected = [2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 0.0, 0.0];
+        assert_eq!(host.audio_output_data(&output), &expected[..]);
+        assert_eq!(host.frames_processed(), 8);
+
+        host.run(&mut gain, 1).unwrap();
+        assert_eq!(host.audio_output_data(&output).len(), 12);
+    }
+
+    struct Echo {
+        input:  InputPortHandle<MidiEvent>,
+        output: OutputPortHandle<MidiEvent>,
+    }
+
+    impl ProcessHandler for Echo {
+        fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+            let events = self.input.get_read_buffer(nframes, ctx);
+            let mut writer = self.output.get_write_buffer(nframes, ctx);
+            for i in 0..events.len() {
+                let e = events.get(i);
+                writer.write(e.get_jack_time(), e.raw_midi_bytes()).unwrap();
+            }
+
+            0
+        }
+    }
+
+    #[test]
+    fn midi_across_cycles() {
+        let mut host = OfflineHost::new(8);
+        let events = vec![(20, vec![0x80, 60, 0]), (3, vec![0x90, 60, 100])];
+        let input = host.midi_input(&events);
+        let output = host.midi_output();
+
+        let mut echo = Echo { input: input, output: output };
+        host.run_to_end(&mut echo).unwrap();
+
+        let expected = vec![(3, vec![0x90, 60, 100]), (20, vec![0x80, 60, 0])];
+        assert_eq!(host.midi_output_data(&echo.output), &expected[..]);
+        assert_eq!(host.frames_processed(), 24);
+    }
+
+    struct WriteRules {
+        output: OutputPortHandle<MidiEvent>,
+    }
+
+    impl ProcessHandler for WriteRules {
+        fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+            let mut writer = self.output.get_write_buffer(nframes, ctx);
+            writer.write(4, &[0xf8]).unwrap();
+
+            // events must be in order, inside the cycle, and fit in the buffer
+            assert!(writer.write(3, &[0xf8]).is_err());
+            assert!(writer.write(nframes, &[0xf8]).is_err());
+
+            let big = vec![0xf0; writer.max_event_size() + 1];
+            assert!(writer.write(5, &big).is_err());
+            assert_eq!(writer.lost_event_count(), 1);
+            0
+        }
+    }
+
+    #[test]
+    fn midi_write_rules() {
+        let mut host = OfflineHost::new(8);
+        let output = host.midi_output();
+
+        let mut rules = WriteRules { output: output };
+        host.run(&mut rules, 2).unwrap();
+
+        // the writer clears the buffer every cycle
+        let expected = vec![(4, vec![0xf8]), (12, vec![0xf8])];
+        assert_eq!(host.midi_output_data(&rules.output), &expected[..]);
+    }
+
+    struct TwoWriters {
+        output: OutputPortHandle<MidiEvent>,
+    }
+
+    impl ProcessHandler for TwoWriters {
+        fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+            // nothing stops a handler from having two writers for a port
+            let mut first = self.output.get_write_buffer(nframes, ctx);
+            first.write(4, &[0xf8]).unwrap();
+
+            // the second writer clears the buffer, as it would in jack
+            let mut second = self.output.get_write_buffer(nframes, ctx);
+            second.write(2, &[0xfa]).unwrap();
+            first.write(5, &[0xfc]).unwrap();
+            assert_eq!(second.lost_event_count(), 0);
+            0
+        }
+    }
+
+    #[test]
+    fn two_midi_writers() {
+        let mut host = OfflineHost::new(8);
+        let output = host.midi_output();
+
+        let mut writers = TwoWriters { output: output };
+        host.run(&mut writers, 1).unwrap();
+
+        let expected = vec![(2, vec![0xfa]), (5, vec![0xfc])];
+        assert_eq!(host.midi_output_data(&writers.output), &expected[..]);
+    }
+
+    struct FailAfter(usize);
+
+    impl ProcessHandler for FailAfter {
+        fn process(&mut self, _: &CallbackContext, _: NumFrames) -> i32 {
+            if self.0 == 0 {
+                return -1;
+            }
+
+            self.0 -= 1;
+            0
+        }
+    }
+
+    #[test]
+    fn handler_failure_stops_run() {
+        let mut host = OfflineHost::new(16);
+        assert_eq!(host.run(&mut FailAfter(2), 5), Err(-1));
+        assert_eq!(host.frames_processed(), 48);
+    }
+
+    #[test]
+    #[should_panic(expected = "OfflineHost")]
+    fn host_port_kept_from_jack() {
+        let mut host = OfflineHost::new(16);
+        host.audio_output().get_name();
+    }
+
+    #[test]
+    #[should_panic(expected = "does not belong")]
+    fn foreign_port() {
+        let mut host = OfflineHost::new(16);
+        let mut other = OfflineHost::new(16);
+        other.audio_input(&[]);
+        let input = other.audio_input(&[]);
+
+        let output = host.audio_output();
+        host.run(&mut Gain { input: input, output: output }, 1).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+//! This module contains a store of named parameters which can be written from any thread and
+//! read from the process callback without locking or allocating.
+//!
+//! Parameters are declared up front with a `ParamsBuilder`, which hands out a typed id for each
+//! parameter. The resulting `Params` can be cloned cheaply; give one clone to the process handler
+//! and keep the others wherever the parameters are controlled from (a UI thread, a network
+//! thread, a metadata handler, etc).
+//!
+//! ```no_run
+//! use easyjack::params::ParamsBuilder;
+//!
+//! let mut builder = ParamsBuilder::new();
+//! let gain = builder.float("gain", 1.0);
+//! let mute = builder.boolean("mute", false);
+//! let params = builder.build();
+//!
+//! // in the process handler
+//! let g = if params.get_bool(mute) { 0.0 } else { params.get_f32(gain) };
+//!
+//! // anywhere else
+//! params.set_f32(gain, 0.5);
+//! ```
+//!
+//! Sudden changes to a parameter like a gain cause audible clicks ("zipper noise"). A
+//! `SmoothedParam` can be used in the process handler to ramp between values instead.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The type of a parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Float,
+    Bool,
+    Int,
+}
+
+/// Identifies a floating point parameter in a `Params`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloatParam(usize);
+
+/// Identifies a boolean parameter in a `Params`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoolParam(usize);
+
+/// Identifies an integer parameter in a `Params`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntParam(usize);
+
+struct Param {
+    name:  String,
+    kind:  ParamKind,
+
+    // every kind of parameter is stored as raw bits
+    value: AtomicU32,
+}
+
+/// Declares the parameters which will be held by a `Params`
+pub struct ParamsBuilder {
+    params: Vec<Param>,
+}
+
+impl ParamsBuilder {
+    pub fn new() -> Self {
+        ParamsBuilder { params: Vec::new() }
+    }
+
+    fn add(&mut self, name: &str, kind: ParamKind, bits: u32) -> usize {
+        assert!(self.params.iter().all(|p| p.name != name), "duplicate parameter name {}", name);
+
+        self.params.push(Param {
+            name:  name.to_string(),
+            kind:  kind,
+            value: AtomicU32::new(bits),
+        });
+
+        self.params.len() - 1
+    }
+
+    /// Declares a floating point parameter with the given initial value.
+    /// Panics if the name has already been used.
+    pub fn float(&mut self, name: &str, initial: f32) -> FloatParam {
+        FloatParam(self.add(name, ParamKind::Float, initial.to_bits()))
+    }
+
+    /// Declares a boolean parameter with the given initial value.
+    /// Panics if the name has already been used.
+    pub fn boolean(&mut self, name: &str, initial: bool) -> BoolParam {
+        BoolParam(self.add(name, ParamKind::Bool, initial as u32))
+    }
+
+    /// Declares an integer parameter with the given initial value.
+    /// Panics if the name has already been used.
+    pub fn int(&mut self, name: &str, initial: i32) -> IntParam {
+        IntParam(self.add(name, ParamKind::Int, initial as u32))
+    }
+
+    /// Creates the parameter store
+    pub fn build(self) -> Params {
+        Params { params: Arc::new(self.params) }
+    }
+}
+
+impl Default for ParamsBuilder {
+    fn default() -> Self { ParamsBuilder::new() }
+}
+
+/// A set of named parameters. See the module documentation for details.
+///
+/// All of the getters and setters are wait-free and never allocate. Each parameter is updated
+/// atomically, but there is no ordering between updates to different parameters.
+#[derive(Clone)]
+pub struct Params {
+    params: Arc<Vec<Param>>,
+}
+
+impl Params {
+    fn lookup(&self, name: &str, kind: ParamKind) -> Option<usize> {
+        self.params.iter().position(|p| p.name == name && p.kind == kind)
+    }
+
+    fn load(&self, i: usize) -> u32 {
+        self.params[i].value.load(Ordering::Relaxed)
+    }
+
+    fn store(&self, i: usize, bits: u32) {
+        self.params[i].value.store(bits, Ordering::Relaxed)
+    }
+
+    /// Returns the names and kinds of all of the parameters, in the order they were declared
+    pub fn names(&self) -> Vec<(&str, ParamKind)> {
+        self.params.iter().map(|p| (&p.name[..], p.kind)).collect()
+    }
+
+    /// Looks up a floating point parameter by name
+    pub fn float_id(&self, name: &str) -> Option<FloatParam> {
+        self.lookup(name, ParamKind::Float).map(FloatParam)
+    }
+
+    /// Looks up a boolean parameter by name
+    pub fn bool_id(&self, name: &str) -> Option<BoolParam> {
+        self.lookup(name, ParamKind::Bool).map(BoolParam)
+    }
+
+    /// Looks up an integer parameter by name
+    pub fn int_id(&self, name: &str) -> Option<IntParam> {
+        self.lookup(name, ParamKind::Int).map(IntParam)
+    }
+
+    pub fn get_f32(&self, id: FloatParam) -> f32 { f32::from_bits(self.load(id.0)) }
+    pub fn get_bool(&self, id: BoolParam) -> bool { self.load(id.0) != 0 }
+    pub fn get_i32(&self, id: IntParam) -> i32 { self.load(id.0) as i32 }
+
+    pub fn set_f32(&self, id: FloatParam, v: f32) { self.store(id.0, v.to_bits()) }
+    pub fn set_bool(&self, id: BoolParam, v: bool) { self.store(id.0, v as u32) }
+    pub fn set_i32(&self, id: IntParam, v: i32) { self.store(id.0, v as u32) }
+}
+
+/// Smooths a floating point parameter by ramping linearly to each new value over a fixed number
+/// of frames.
+///
+/// Call `update` once per cycle (to pick up the latest value of the parameter), then `next` once
+/// per frame.
+pub struct SmoothedParam {
+    id:        FloatParam,
+    ramp:      u32,
+    current:   f32,
+    target:    f32,
+    step:      f32,
+    remaining: u32,
+}
+
+impl SmoothedParam {
+    /// Creates a smoother for the given parameter, starting at its current value. Changes are
+    /// spread over `ramp_frames` frames (a ramp of zero frames disables smoothing).
+    pub fn new(params: &Params, id: FloatParam, ramp_frames: u32) -> Self {
+        let v = params.get_f32(id);
+        SmoothedParam {
+            id:        id,
+            ramp:      ramp_frames,
+            current:   v,
+            target:    v,
+            step:      0.0,
+            remaining: 0,
+        }
+    }
+
+    /// Reads the latest value of the parameter. If it has changed, a new ramp starts from the
+    /// current (possibly partially ramped) value.
+    pub fn update(&mut self, params: &Params) {
+        let target = params.get_f32(self.id);
+        if target == self.target {
+            return;
+        }
+
+        self.target = target;
+        if self.ramp == 0 {
+            self.current = target;
+            self.remaining = 0;
+        } else {
+            self.step = (target - self.current) / self.ramp as f32;
+            self.remaining = self.ramp;
+        }
+    }
+
+    /// Returns the value for the next frame
+    pub fn next(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 { self.target } else { self.current + self.step };
+        }
+
+        self.current
+    }
+
+    /// Returns true while a ramp is in progress
+    pub fn is_ramping(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// Fills `buf` with the values for the next `buf.len()` frames
+    pub fn fill(&mut self, buf: &mut [f32]) {
+        for v in buf.iter_mut() {
+            *v = self.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn get_and_set() {
+        let mut b = ParamsBuilder::new();
+        let gain = b.float("gain", 0.5);
+        let mute = b.boolean("mute", true);
+        let mode = b.int("mode", -3);
+        let params = b.build();
+
+        assert_eq!(params.get_f32(gain), 0.5);
+        assert_eq!(params.get_bool(mute), true);
+        assert_eq!(params.get_i32(mode), -3);
+
+        let other = params.clone();
+        thread::spawn(move || {
+            other.set_f32(gain, -2.25);
+            other.set_bool(mute, false);
+            other.set_i32(mode, 7);
+        }).join().unwrap();
+
+        assert_eq!(params.get_f32(gain), -2.25);
+        assert_eq!(params.get_bool(mute), false);
+        assert_eq!(params.get_i32(mode), 7);
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        let mut b = ParamsBuilder::new();
+        let gain = b.float("gain", 0.0);
+        let mute = b.boolean("mute", false);
+        let params = b.build();
+
+        assert_eq!(params.float_id("gain"), Some(gain));
+        assert_eq!(params.bool_id("mute"), Some(mute));
+        assert_eq!(params.int_id("gain"), None);
+        assert_eq!(params.float_id("nope"), None);
+        assert_eq!(params.names(), vec![("gain", ParamKind::Float), ("mute", ParamKind::Bool)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate_names() {
+        let mut b = ParamsBuilder::new();
+        b.float("gain", 0.0);
+        b.int("gain", 0);
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut b = ParamsBuilder::new();
+        let gain = b.float("gain", 0.0);
+        let params = b.build();
+
+        let mut s = SmoothedParam::new(&params, gain, 4);
+        s.update(&params);
+        assert_eq!(s.next(), 0.0);
+
+        params.set_f32(gain, 1.0);
+        s.update(&params);
+
+        let mut buf = [0.0; 6];
+        s.fill(&mut buf);
+        assert_eq!(buf, [0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert!(!s.is_ramping());
+
+        // no smoothing
+        let mut s = SmoothedParam::new(&params, gain, 0);
+        params.set_f32(gain, 3.0);
+        s.update(&params);
+        assert_eq!(s.next(), 3.0);
+    }
+}
@@ -1,6 +1,8 @@
+use ffi;
 use jack_sys;
+use libc;
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
 use std::slice;
 use std::str::FromStr;
@@ -9,9 +11,18 @@ use num;
 use midi::*;
 use types::*;
 use callbackhandler::*;
+use offline::is_host_port;
 
 type Jackptr = *mut jack_sys::jack_port_t;
 
+/// Returns the port's pointer for handing to libjack. Panics for the handles an `OfflineHost`
+/// gives out, which only stand in for ports inside its own cycles.
+pub(crate) fn jack_port<P: Port + ?Sized>(port: &P) -> Jackptr {
+    let raw = unsafe { port.get_raw() };
+    assert!(!is_host_port(raw), "port belongs to an OfflineHost, not to JACK");
+    raw
+}
+
 /// Ports are the means by which jack clients communicate with each other.
 ///
 /// The port wrappers in `easyjack` have slightly confusing type definitions due to the behavior of
@@ -49,23 +60,134 @@ pub trait Port {
     /// Gets the port's assigned full name (including the client name and the colon)
     fn get_name(&self) -> String {
         unsafe {
-            let raw = self.get_raw();
-            let cstr = jack_sys::jack_port_name(raw);
+            let cstr = ffi::jack_port_name(jack_port(self));
 
             // do a little dance to alleviate ownership pains
             String::from_str(CStr::from_ptr(cstr).to_str().unwrap()).unwrap()
         }
     }
 
+    /// Gets the port's UUID. The UUID can be used as the subject of metadata properties (see
+    /// `Client::set_property`).
+    fn get_uuid(&self) -> UUID {
+        unsafe { ffi::jack_port_uuid(jack_port(self)) }
+    }
+
+    /// Gets the aliases assigned to this port. JACK allows each port to have at most two aliases,
+    /// so the returned vector will have between zero and two entries.
+    ///
+    /// Hardware ports frequently have useful aliases (ALSA device names, for example).
+    fn aliases(&self) -> Vec<String> {
+        // jack writes the aliases into buffers we provide, each of which must be able to hold a
+        // full port name
+        let size = unsafe { ffi::jack_port_name_size() } as usize;
+        let mut a1 = vec![0 as libc::c_char; size];
+        let mut a2 = vec![0 as libc::c_char; size];
+        let mut ptrs = [a1.as_mut_ptr(), a2.as_mut_ptr()];
+
+        let cnt = unsafe { ffi::jack_port_get_aliases(jack_port(self), ptrs.as_mut_ptr()) };
+        if cnt < 0 {
+            return Vec::new();
+        }
+
+        ptrs.iter()
+            .take(cnt as usize)
+            .map(|&p| unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Adds an alias to this port. Fails if the port already has two aliases.
+    fn set_alias(&self, alias: &str) -> Result<(), status::Status> {
+        let cstr = CString::new(alias).unwrap();
+        let ret = unsafe { ffi::jack_port_set_alias(jack_port(self), cstr.as_ptr()) };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            // no error code is returned from jack here
+            Err(status::FAILURE)
+        }
+    }
+
+    /// Removes the given alias from this port
+    fn unset_alias(&self, alias: &str) -> Result<(), status::Status> {
+        let cstr = CString::new(alias).unwrap();
+        let ret = unsafe { ffi::jack_port_unset_alias(jack_port(self), cstr.as_ptr()) };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(status::FAILURE)
+        }
+    }
+
+    /// Gets the latency range of this port for the given mode.
+    ///
+    /// This is most useful inside of a latency callback (see `MetadataHandler::on_latency`), or
+    /// after calling `Client::recompute_total_latencies`.
+    fn get_latency_range(&self, mode: LatencyMode) -> LatencyRange {
+        let mut range = jack_sys::jack_latency_range_t::default();
+        unsafe {
+            ffi::jack_port_get_latency_range(jack_port(self), mode.to_raw(), &mut range)
+        };
+
+        LatencyRange { min: range.min, max: range.max }
+    }
+
+    /// Sets the latency range of this port for the given mode.
+    ///
+    /// This should only be called from inside of a latency callback (see
+    /// `MetadataHandler::on_latency`). Clients which add latency (delay lines, lookahead, etc) must
+    /// report it here so that the rest of the graph can compensate for it.
+    fn set_latency_range(&self, mode: LatencyMode, range: LatencyRange) {
+        let mut range = jack_sys::jack_latency_range_t { min: range.min, max: range.max };
+        unsafe {
+            ffi::jack_port_set_latency_range(jack_port(self), mode.to_raw(), &mut range)
+        };
+    }
+
     // TODO many other functions
 
     /// Get the flags used to construct this port
     fn get_port_flags(&self) -> port_flags::PortFlags {
-        let rawbits = unsafe { jack_sys::jack_port_flags(self.get_raw()) };
+        let rawbits = unsafe { ffi::jack_port_flags(jack_port(self)) };
         port_flags::PortFlags::from_bits(rawbits as u32).unwrap()
     }
 }
 
+/// Ties the type a port's buffer holds to the JACK port type of the port.
+///
+/// `Client::register_input_port` and `Client::register_output_port` use this to decide which type
+/// of port to register, so the handle they return always matches the port. Implement it for your
+/// own types to register ports with custom types, for example:
+///
+/// ```
+/// # use easyjack::PortSample;
+/// #[repr(C)]
+/// struct Rgba { r: f32, g: f32, b: f32, a: f32 }
+///
+/// unsafe impl PortSample for Rgba {
+///     const PORT_TYPE: &'static str = "32 bit float RGBA video";
+/// }
+/// ```
+///
+/// This is unsafe to implement because the buffer of every port of type `PORT_TYPE` is read as
+/// `Self`. The type must have a layout every client using `PORT_TYPE` agrees on (usually
+/// `#[repr(C)]`), and for types which also implement `num::Num`, the buffer must hold one `Self`
+/// per frame.
+pub unsafe trait PortSample {
+    /// The JACK type string of ports whose buffers hold this type
+    const PORT_TYPE: PortType;
+}
+
+unsafe impl PortSample for DefaultAudioSample {
+    const PORT_TYPE: PortType = port_type::DEFAULT_AUDIO_TYPE;
+}
+
+unsafe impl PortSample for MidiEvent {
+    const PORT_TYPE: PortType = port_type::DEFAULT_MIDI_TYPE;
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct UnknownPortHandle {
     c_port: Jackptr
@@ -144,29 +266,55 @@ impl<SampleType> Port for InputPortHandle<SampleType> {
 
 impl<SampleType: num::Num> InputPortHandle<SampleType> {
     /// Get the input port's readable buffer
-    pub fn get_read_buffer<'a>(&self, nframes: NumFrames, _ctx: &'a CallbackContext)
+    pub fn get_read_buffer<'a>(&self, nframes: NumFrames, ctx: &'a CallbackContext)
         -> &'a [SampleType]
     {
         unsafe {
-            let ptr = jack_sys::jack_port_get_buffer(self.c_port, nframes);
+            let ptr = ctx.port_buffer(self.c_port, nframes);
             let ptr = ptr as *mut SampleType;
             slice::from_raw_parts_mut(ptr, nframes as usize)
         }
     }
 }
 
+impl<SampleType> InputPortHandle<SampleType> {
+    /// Get the input port's buffer as a slice of `len` elements of `SampleType`.
+    ///
+    /// This is intended for ports with custom types (see `Client::register_input_port`), where
+    /// the buffer holds something other than one sample per frame.
+    ///
+    /// This is unsafe because it cannot check that the port's buffer is large enough to hold `len`
+    /// elements of `SampleType`. The buffer size of a port type can be found with
+    /// `Client::get_port_type_buffer_size`.
+    pub unsafe fn get_read_elements<'a>(
+        &self,
+        nframes: NumFrames,
+        len: usize,
+        ctx: &'a CallbackContext)
+        -> &'a [SampleType]
+    {
+        let ptr = ctx.port_buffer(self.c_port, nframes);
+        slice::from_raw_parts(ptr as *const SampleType, len)
+    }
+}
+
 impl InputPortHandle<MidiEvent> {
     /// returns a vector of midi events
     /// Note that this returns by value (we are not returning by reference, like we have in the
     /// other `get_read_buffer` methods)
-    pub fn get_read_buffer<'a>(&self, nframes:NumFrames, _ctx: &'a CallbackContext)
+    pub fn get_read_buffer<'a>(&self, nframes:NumFrames, ctx: &'a CallbackContext)
         -> MidiEventBuf<'a>
     {
         // getting a buffer of midi events is much harder than getting a buffer of audio events,
         // but it's okay, we can make it work!
         unsafe {
+            let buffer = ctx.host_midi(self.c_port);
+            if !buffer.is_null() {
+                return MidiEventBuf::from_host(&*buffer);
+            }
+
             // first, get the raw event port from jack
-            let ptr = jack_sys::jack_port_get_buffer(self.c_port, nframes);
+            let ptr = ctx.port_buffer(self.c_port, nframes);
             MidiEventBuf::new(ptr)
         }
     }
@@ -191,17 +339,59 @@ impl<SampleType> Port for OutputPortHandle<SampleType> {
     unsafe fn get_raw(&self) -> Jackptr { self.c_port }
 }
 
-impl<SampleType> OutputPortHandle<SampleType> {
-    /// Get the input port's readable buffer
-    pub fn get_write_buffer<'a>(&self, nframes: NumFrames, _ctx: &'a CallbackContext)
+impl<SampleType: num::Num> OutputPortHandle<SampleType> {
+    /// Get the output port's writable buffer
+    pub fn get_write_buffer<'a>(&self, nframes: NumFrames, ctx: &'a CallbackContext)
         -> &'a mut [SampleType]
     {
         unsafe {
-            let ptr = jack_sys::jack_port_get_buffer(self.c_port, nframes);
+            let ptr = ctx.port_buffer(self.c_port, nframes);
             let ptr = ptr as *mut SampleType;
             slice::from_raw_parts_mut(ptr, nframes as usize)
         }
     }
 }
 
+impl OutputPortHandle<MidiEvent> {
+    /// returns a writer which can be used to write midi events to the port for this cycle
+    /// The port's buffer is cleared when the writer is created, so this should only be called once
+    /// per cycle.
+    pub fn get_write_buffer<'a>(&self, nframes: NumFrames, ctx: &'a CallbackContext)
+        -> MidiEventWriter<'a>
+    {
+        unsafe {
+            let buffer = ctx.host_midi(self.c_port);
+            if !buffer.is_null() {
+                return MidiEventWriter::to_host(buffer);
+            }
+
+            let ptr = ctx.port_buffer(self.c_port, nframes);
+            MidiEventWriter::new(ptr)
+        }
+    }
+}
+
+impl<SampleType> OutputPortHandle<SampleType> {
+    /// Get the output port's buffer as a slice of `len` elements of `SampleType`.
+    ///
+    /// This is intended for ports with custom types (see `Client::register_output_port`), where
+    /// the buffer holds something other than one sample per frame.
+    ///
+    /// This is unsafe because it cannot check that the port's buffer is large enough to hold `len`
+    /// elements of `SampleType`. The buffer size of a port type can be found with
+    /// `Client::get_port_type_buffer_size`. As with `get_write_buffer`, only one slice of a port's
+    /// buffer may be in use at a time, so call this once per port per cycle.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_write_elements<'a>(
+        &self,
+        nframes: NumFrames,
+        len: usize,
+        ctx: &'a CallbackContext)
+        -> &'a mut [SampleType]
+    {
+        let ptr = ctx.port_buffer(self.c_port, nframes);
+        slice::from_raw_parts_mut(ptr as *mut SampleType, len)
+    }
+}
+
 // TODO some nice type aliases to hide all this magic and craziness
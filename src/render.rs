@@ -0,0 +1,277 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use callbackhandler::*;
+use client::*;
+use port::*;
+use types::*;
+
+/// Receives the audio captured by `render_offline`
+pub trait RenderSink {
+    /// Called with each block of captured audio. There is one slice per captured port, in the
+    /// order the ports were given, and all of the slices are the same length.
+    fn write(&mut self, channels: &[&[DefaultAudioSample]]) -> io::Result<()>;
+
+    /// Called once after the last block has been written
+    fn finish(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// The ways `render_offline` can fail
+#[derive(Debug)]
+pub enum RenderError {
+    /// A call into JACK failed
+    Jack(status::Status),
+
+    /// The sink returned an error
+    Io(io::Error),
+
+    /// Freewheel mode was turned off (by another client), or the server shut down, before the
+    /// render finished
+    Interrupted,
+
+    /// The render took far longer than `duration`, see `render_offline`
+    TimedOut,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::Jack(ref s) => write!(f, "jack error: {:?}", s),
+            RenderError::Io(ref e)   => write!(f, "render sink error: {}", e),
+            RenderError::Interrupted => write!(f, "freewheel mode ended before render finished"),
+            RenderError::TimedOut    => write!(f, "render did not finish in time"),
+        }
+    }
+}
+
+impl error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RenderError::Io(ref e) => Some(e),
+            _                      => None,
+        }
+    }
+}
+
+impl From<status::Status> for RenderError {
+    fn from(s: status::Status) -> Self { RenderError::Jack(s) }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(e: io::Error) -> Self { RenderError::Io(e) }
+}
+
+/// How many times longer than the rendered duration `render_offline` waits before giving up
+pub const RENDER_TIMEOUT_FACTOR: u32 = 4;
+
+/// Extra time `render_offline` waits on top of `RENDER_TIMEOUT_FACTOR`, so that short renders are
+/// not cut off by the time it takes to start freewheeling
+pub const RENDER_TIMEOUT_SLACK: Duration = Duration::from_secs(10);
+
+struct CaptureState<S> {
+    sink:      Option<S>,
+    remaining: u64,
+    result:    Option<Result<(), RenderError>>,
+}
+
+// shared between the process thread, the notification thread, and the thread waiting for the
+// render to finish
+struct Shared<S> {
+    freewheeling: AtomicBool,
+    state:        Mutex<CaptureState<S>>,
+    done:         Condvar,
+}
+
+impl<S> Shared<S> {
+    fn lock(&self) -> MutexGuard<'_, CaptureState<S>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn finish(&self, state: &mut CaptureState<S>, result: Result<(), RenderError>) {
+        if state.result.is_none() {
+            state.result = Some(result);
+            self.done.notify_all();
+        }
+    }
+}
+
+// the process handler. Nothing is captured until the server is freewheeling, at which point
+// there is no deadline and it is fine to take a lock and call into the sink
+struct Capture<S> {
+    ports:  Vec<InputPortHandle<DefaultAudioSample>>,
+    views:  Vec<*const [DefaultAudioSample]>,
+    shared: Arc<Shared<S>>,
+}
+
+impl<S: RenderSink> ProcessHandler for Capture<S> {
+    fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+        if !self.shared.freewheeling.load(Ordering::SeqCst) {
+            return 0;
+        }
+
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+        if state.result.is_some() {
+            return 0;
+        }
+
+        let n = ::std::cmp::min(nframes as u64, state.remaining) as usize;
+
+        self.views.clear();
+        for p in &self.ports {
+            self.views.push(&p.get_read_buffer(nframes, ctx)[..n] as *const [DefaultAudioSample]);
+        }
+
+        // the views point at jack's buffers, which are valid for the rest of the cycle
+        let channels = unsafe {
+            ::std::slice::from_raw_parts(
+                self.views.as_ptr() as *const &[DefaultAudioSample],
+                self.views.len())
+        };
+
+        let written = state.sink.as_mut().map(|s| s.write(channels)).unwrap_or(Ok(()));
+        state.remaining -= n as u64;
+
+        match written {
+            Err(e) => shared.finish(&mut state, Err(RenderError::Io(e))),
+            Ok(()) if state.remaining == 0 => shared.finish(&mut state, Ok(())),
+            Ok(()) => (),
+        }
+
+        0
+    }
+}
+
+struct FreewheelWatcher<S> {
+    shared: Arc<Shared<S>>,
+}
+
+impl<S> MetadataHandler for FreewheelWatcher<S> {
+    fn on_freewheel(&mut self, starting: bool) {
+        self.shared.freewheeling.store(starting, Ordering::SeqCst);
+        if !starting {
+            let mut state = self.shared.lock();
+            self.shared.finish(&mut state, Err(RenderError::Interrupted));
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        let mut state = self.shared.lock();
+        self.shared.finish(&mut state, Err(RenderError::Interrupted));
+    }
+
+    fn callbacks_of_interest(&self) -> Vec<MetadataHandlers> {
+        vec![MetadataHandlers::Freewheel, MetadataHandlers::Shutdown]
+    }
+}
+
+// owns the temporary client. Turns freewheel mode back off (if it was turned on) and closes the
+// client when dropped, so every exit path from render_offline cleans up
+struct Session<'a> {
+    client:       Client<'a>,
+    freewheeling: bool,
+}
+
+impl<'a> Drop for Session<'a> {
+    fn drop(&mut self) {
+        if self.freewheeling {
+            let _ = self.client.set_freewheel(false);
+        }
+
+        let _ = self.client.deactivate();
+        let _ = self.client.close();
+    }
+}
+
+/// Renders `duration` worth of audio from the named ports into `sink`, faster than realtime.
+///
+/// This opens a temporary client, connects it to the ports, and switches the server into
+/// freewheel mode until exactly `duration` (rounded to the nearest frame) has been captured. The
+/// server is switched back to realtime mode before returning, whether or not the render
+/// succeeded. On success, the sink is returned after its `finish` method has been called.
+///
+/// While freewheeling, the process thread has no deadline, so the sink is called directly from
+/// it and may block (to write to a file, for example).
+///
+/// Freewheel mode affects the whole server: every other client's process callback will also run
+/// faster than realtime, and audio hardware will not be serviced until the render finishes.
+///
+/// Freewheeling is normally much faster than realtime, so if the render takes more than
+/// `RENDER_TIMEOUT_FACTOR` times `duration` (plus `RENDER_TIMEOUT_SLACK`) something has gone wrong,
+/// and `RenderError::TimedOut` is returned.
+pub fn render_offline<S>(duration: Duration, ports: &[&str], sink: S) -> Result<S, RenderError>
+    where S: RenderSink + Send
+{
+    let (client, _) = Client::open("easyjack_render", options::NO_START_SERVER)?;
+    let mut session = Session { client: client, freewheeling: false };
+
+    let srate = session.client.get_sample_rate() as f64;
+    let frames = (duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9) * srate;
+    let frames = frames.round() as u64;
+
+    let shared = Arc::new(Shared {
+        freewheeling: AtomicBool::new(false),
+        state:        Mutex::new(CaptureState {
+            sink:      Some(sink),
+            remaining: frames,
+            result:    if frames == 0 { Some(Ok(())) } else { None },
+        }),
+        done:         Condvar::new(),
+    });
+
+    let mut handles = Vec::with_capacity(ports.len());
+    for i in 0..ports.len() {
+        handles.push(session.client.register_input_audio_port(&format!("in_{}", i + 1))?);
+    }
+
+    let names: Vec<String> = handles.iter().map(|p| p.get_name()).collect();
+
+    session.client.set_process_handler(Capture {
+        ports:  handles,
+        views:  Vec::with_capacity(ports.len()),
+        shared: shared.clone(),
+    })?;
+
+    session.client.set_metadata_handler(FreewheelWatcher { shared: shared.clone() })?;
+    session.client.activate()?;
+
+    for (src, dst) in ports.iter().zip(names.iter()) {
+        session.client.connect_ports(src, dst)?;
+    }
+
+    session.client.set_freewheel(true)?;
+    session.freewheeling = true;
+
+    // if the server was already freewheeling, no notification is coming
+    shared.freewheeling.store(true, Ordering::SeqCst);
+
+    let deadline = Instant::now() + duration * RENDER_TIMEOUT_FACTOR + RENDER_TIMEOUT_SLACK;
+    let result = {
+        let mut state = shared.lock();
+        while state.result.is_none() {
+            let now = Instant::now();
+            if now >= deadline {
+                shared.finish(&mut state, Err(RenderError::TimedOut));
+                break;
+            }
+
+            let (s, _) = shared.done.wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner());
+            state = s;
+        }
+
+        state.result.take().unwrap()
+    };
+
+    // restore realtime mode and close the client before touching the sink again
+    drop(session);
+    result?;
+
+    let mut sink = shared.lock().sink.take().unwrap();
+    sink.finish()?;
+    Ok(sink)
+}
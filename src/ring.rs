@@ -0,0 +1,128 @@
+//! A fixed size, lock free, single producer single consumer queue.
+//!
+//! This is used to move small records out of the process callback. Pushing never blocks or
+//! allocates; if the queue is full the push fails and the caller decides what to do about it.
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+
+    // both indices increase forever (wrapping), the slot is found with a modulus.
+    // head is only written by the producer, tail is only written by the consumer
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// the producer and consumer never touch the same slot at the same time
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+/// The writing half of the queue
+pub(crate) struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// The reading half of the queue
+pub(crate) struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// Creates a queue which can hold `capacity` items
+pub(crate) fn ring<T: Copy + Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "queue capacity must not be zero");
+
+    let slots = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+    let ring = Arc::new(Ring {
+        slots: slots,
+        head:  AtomicUsize::new(0),
+        tail:  AtomicUsize::new(0),
+    });
+
+    (Producer { ring: ring.clone() }, Consumer { ring: ring })
+}
+
+impl<T: Copy + Send> Producer<T> {
+    /// Pushes an item onto the queue. Returns the item if the queue is full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let ring = &*self.ring;
+        let head = ring.head.load(Ordering::Relaxed);
+        let tail = ring.tail.load(Ordering::Acquire);
+
+        if head.wrapping_sub(tail) == ring.slots.len() {
+            return Err(item);
+        }
+
+        unsafe {
+            (*ring.slots[head % ring.slots.len()].get()) = MaybeUninit::new(item);
+        }
+
+        ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<T: Copy + Send> Consumer<T> {
+    /// Pops the oldest item from the queue, if there is one
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Relaxed);
+        let head = ring.head.load(Ordering::Acquire);
+
+        if tail == head {
+            return None;
+        }
+
+        let item = unsafe { (*ring.slots[tail % ring.slots.len()].get()).assume_init() };
+        ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn fills_and_drains() {
+        let (mut p, mut c) = ring::<u32>(3);
+        assert_eq!(c.pop(), None);
+
+        assert!(p.push(1).is_ok());
+        assert!(p.push(2).is_ok());
+        assert!(p.push(3).is_ok());
+        assert_eq!(p.push(4), Err(4));
+
+        assert_eq!(c.pop(), Some(1));
+        assert!(p.push(5).is_ok());
+        assert_eq!(c.pop(), Some(2));
+        assert_eq!(c.pop(), Some(3));
+        assert_eq!(c.pop(), Some(5));
+        assert_eq!(c.pop(), None);
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut p, mut c) = ring::<usize>(16);
+        let t = thread::spawn(move || {
+            for i in 0..10000 {
+                while p.push(i).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < 10000 {
+            if let Some(i) = c.pop() {
+                assert_eq!(i, expected);
+                expected += 1;
+            }
+        }
+
+        t.join().unwrap();
+    }
+}
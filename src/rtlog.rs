@@ -0,0 +1,263 @@
+//! This module contains a logger which is safe to use from the process callback.
+//!
+//! Formatting a message and writing it somewhere can allocate, take locks, and make system calls,
+//! any of which can cause the process callback to miss its deadline. Instead, the `RtLogger`
+//! pushes small fixed-size records (a static message template and a few numeric arguments) onto
+//! a preallocated lock free queue. An `RtLogDrain`, usually running on a background thread, pops
+//! the records, formats them, and hands them to the `log` crate.
+//!
+//! If the queue is full, the record is dropped and counted. The drain reports the number of
+//! dropped records the next time it runs.
+//!
+//! ```no_run
+//! use easyjack::rtlog;
+//! use std::time::Duration;
+//!
+//! let (mut logger, drain) = rtlog::channel(256);
+//! let _thread = drain.spawn(Duration::from_millis(50));
+//!
+//! // in the process callback
+//! logger.warn("clipped {} samples on channel {}", &[12.0, 1.0]);
+//! ```
+
+use log;
+
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use ring::*;
+
+/// The maximum number of arguments a single record can hold
+pub const MAX_ARGS: usize = 4;
+
+/// The target used for records which do not have one set
+pub const DEFAULT_TARGET: &'static str = "easyjack::rtlog";
+
+#[derive(Clone, Copy)]
+struct Record {
+    level:   log::Level,
+    target:  &'static str,
+    message: &'static str,
+    args:    [f64; MAX_ARGS],
+    nargs:   usize,
+}
+
+/// The realtime half of the logger. See the module documentation.
+pub struct RtLogger {
+    queue:    Producer<Record>,
+    target:   &'static str,
+    overflow: Arc<AtomicUsize>,
+}
+
+/// The non-realtime half of the logger. See the module documentation.
+pub struct RtLogDrain {
+    queue:    Consumer<Record>,
+    overflow: Arc<AtomicUsize>,
+
+    // the value of overflow the last time it was reported
+    reported: usize,
+}
+
+/// Creates a logger and a drain connected by a queue which can hold `capacity` records.
+///
+/// All of the memory for the queue is allocated here.
+pub fn channel(capacity: usize) -> (RtLogger, RtLogDrain) {
+    let (p, c) = ring(capacity);
+    let overflow = Arc::new(AtomicUsize::new(0));
+
+    let logger = RtLogger {
+        queue:    p,
+        target:   DEFAULT_TARGET,
+        overflow: overflow.clone(),
+    };
+
+    let drain = RtLogDrain {
+        queue:    c,
+        overflow: overflow,
+        reported: 0,
+    };
+
+    (logger, drain)
+}
+
+impl RtLogger {
+    /// Sets the target the records will be logged with
+    pub fn set_target(&mut self, target: &'static str) {
+        self.target = target;
+    }
+
+    /// Queues a record. Each `{}` in `message` will be replaced by one of `args` when the record
+    /// is formatted. At most `MAX_ARGS` arguments are kept, any others are ignored.
+    ///
+    /// This never blocks or allocates. If the queue is full the record is dropped and counted.
+    pub fn log(&mut self, level: log::Level, message: &'static str, args: &[f64]) {
+        let mut rec = Record {
+            level:   level,
+            target:  self.target,
+            message: message,
+            args:    [0.0; MAX_ARGS],
+            nargs:   if args.len() > MAX_ARGS { MAX_ARGS } else { args.len() },
+        };
+
+        rec.args[..rec.nargs].copy_from_slice(&args[..rec.nargs]);
+
+        if self.queue.push(rec).is_err() {
+            self.overflow.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn error(&mut self, message: &'static str, args: &[f64]) {
+        self.log(log::Level::Error, message, args)
+    }
+
+    pub fn warn(&mut self, message: &'static str, args: &[f64]) {
+        self.log(log::Level::Warn, message, args)
+    }
+
+    pub fn info(&mut self, message: &'static str, args: &[f64]) {
+        self.log(log::Level::Info, message, args)
+    }
+
+    pub fn debug(&mut self, message: &'static str, args: &[f64]) {
+        self.log(log::Level::Debug, message, args)
+    }
+
+    /// Returns the total number of records which have been dropped because the queue was full
+    pub fn overflows(&self) -> usize {
+        self.overflow.load(Ordering::Relaxed)
+    }
+}
+
+// formats a record's message, substituting the arguments.
+// arguments which are whole numbers are printed without a fractional part
+fn format_record(rec: &Record) -> String {
+    let mut out = String::with_capacity(rec.message.len() + 8 * rec.nargs);
+    let mut args = rec.args[..rec.nargs].iter();
+    let mut pieces = rec.message.split("{}");
+
+    if let Some(first) = pieces.next() {
+        out.push_str(first);
+    }
+
+    for piece in pieces {
+        match args.next() {
+            Some(a) if a.fract() == 0.0 && a.abs() < 1e15 => {
+                let _ = write!(out, "{}", *a as i64);
+            },
+
+            Some(a) => { let _ = write!(out, "{}", a); },
+            None    => out.push_str("{}"),
+        }
+
+        out.push_str(piece);
+    }
+
+    out
+}
+
+impl RtLogDrain {
+    /// Formats and logs every queued record. Returns the number of records logged.
+    ///
+    /// If any records were dropped since the last call, a warning saying how many is logged
+    /// first.
+    pub fn drain(&mut self) -> usize {
+        let overflow = self.overflow.load(Ordering::Relaxed);
+        if overflow != self.reported {
+            warn!(target: DEFAULT_TARGET, "dropped {} realtime log records (queue full)",
+                  overflow.wrapping_sub(self.reported));
+            self.reported = overflow;
+        }
+
+        let mut n = 0;
+        while let Some(rec) = self.queue.pop() {
+            if log_enabled!(target: rec.target, rec.level) {
+                log!(target: rec.target, rec.level, "{}", format_record(&rec));
+            }
+
+            n += 1;
+        }
+
+        n
+    }
+
+    /// Starts a thread which drains the queue every `interval`. The thread runs until the returned
+    /// `RtLogThread` is dropped (the queue is drained one last time before it exits).
+    pub fn spawn(mut self, interval: Duration) -> RtLogThread {
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+
+        let handle = thread::spawn(move || {
+            while r.load(Ordering::SeqCst) {
+                self.drain();
+                thread::sleep(interval);
+            }
+
+            self.drain();
+        });
+
+        RtLogThread {
+            running: running,
+            handle:  Some(handle),
+        }
+    }
+}
+
+/// A background thread draining an `RtLogDrain`. Stops the thread when dropped.
+pub struct RtLogThread {
+    running: Arc<AtomicBool>,
+    handle:  Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for RtLogThread {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(message: &'static str, args: &[f64]) -> Record {
+        let mut rec = Record {
+            level:   log::Level::Info,
+            target:  DEFAULT_TARGET,
+            message: message,
+            args:    [0.0; MAX_ARGS],
+            nargs:   args.len(),
+        };
+
+        rec.args[..args.len()].copy_from_slice(args);
+        rec
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_record(&record("no args", &[])), "no args");
+        assert_eq!(format_record(&record("x = {}", &[3.0])), "x = 3");
+        assert_eq!(format_record(&record("{} and {}", &[-1.5, 2.0])), "-1.5 and 2");
+        assert_eq!(format_record(&record("{} {}", &[1.0])), "1 {}");
+    }
+
+    #[test]
+    fn overflow_is_counted() {
+        let (mut logger, mut drain) = channel(2);
+        logger.info("one", &[]);
+        logger.info("two", &[]);
+        logger.info("three", &[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(logger.overflows(), 1);
+
+        assert_eq!(drain.drain(), 2);
+        assert_eq!(drain.reported, 1);
+
+        logger.info("four", &[]);
+        assert_eq!(drain.drain(), 1);
+        assert_eq!(drain.drain(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use ffi;
+use jack_sys;
+use libc;
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use client::*;
+use types::*;
+
+// the closure a new thread starts with. It is double boxed so that it can be passed through a
+// thin void* pointer
+type Start = Box<dyn FnOnce() + Send>;
+
+// where the thread leaves its result (or the payload of its panic) for the joining thread
+type Packet<T> = Arc<Mutex<Option<thread::Result<T>>>>;
+
+unsafe extern "C" fn start_routine(arg: *mut libc::c_void) -> *mut libc::c_void {
+    let f = Box::from_raw(arg as *mut Start);
+    f();
+    ptr::null_mut()
+}
+
+// wraps `f` so that it stores its result in the packet instead of unwinding into C
+fn wrap<F, T>(f: F, packet: Packet<T>) -> Start
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    Box::new(move || {
+        let r = panic::catch_unwind(AssertUnwindSafe(f));
+        *packet.lock().unwrap_or_else(|e| e.into_inner()) = Some(r);
+    })
+}
+
+/// An owned permission to join on a thread created with `spawn_rt`. If it is dropped, the thread
+/// is detached.
+pub struct RtJoinHandle<T> {
+    native: jack_sys::jack_native_thread_t,
+    packet: Packet<T>,
+    joined: bool,
+}
+
+impl<T> RtJoinHandle<T> {
+    /// Waits for the thread to finish. If the thread panicked, returns `Err` with the panic's
+    /// payload, exactly like `std::thread::JoinHandle::join`. Use `std::panic::resume_unwind` to
+    /// continue the panic on the joining thread.
+    pub fn join(mut self) -> thread::Result<T> {
+        unsafe { libc::pthread_join(self.native, ptr::null_mut()) };
+        self.joined = true;
+
+        let r = self.packet.lock().unwrap_or_else(|e| e.into_inner()).take();
+        r.expect("realtime thread exited without a result")
+    }
+
+    /// Returns the underlying pthread handle
+    pub fn native(&self) -> jack_sys::jack_native_thread_t {
+        self.native
+    }
+}
+
+impl<T> Drop for RtJoinHandle<T> {
+    fn drop(&mut self) {
+        if !self.joined {
+            unsafe { libc::pthread_detach(self.native) };
+        }
+    }
+}
+
+/// Spawns a thread with the scheduling JACK would give one of the client's own threads: if the
+/// server is running with realtime scheduling, the thread runs with the client's realtime
+/// priority, otherwise it is an ordinary thread.
+///
+/// A panic in `f` does not take down the process; it is returned from `RtJoinHandle::join`.
+pub fn spawn_rt<F, T>(client: &Client, f: F) -> Result<RtJoinHandle<T>, status::Status>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    match client.real_time_priority() {
+        Some(p) => spawn_with(client, p, true, f),
+        None    => spawn_with(client, 0, false, f),
+    }
+}
+
+/// Spawns a thread with realtime scheduling at the given priority (see
+/// `Client::max_real_time_priority`). Otherwise the same as `spawn_rt`.
+pub fn spawn_rt_with_priority<F, T>(client: &Client, priority: i32, f: F)
+    -> Result<RtJoinHandle<T>, status::Status>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    spawn_with(client, priority, true, f)
+}
+
+fn spawn_with<F, T>(client: &Client, priority: i32, realtime: bool, f: F)
+    -> Result<RtJoinHandle<T>, status::Status>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let packet = Arc::new(Mutex::new(None));
+    let arg = Box::into_raw(Box::new(wrap(f, packet.clone())));
+
+    let mut native: jack_sys::jack_native_thread_t = unsafe { mem::zeroed() };
+    let ret = unsafe {
+        ffi::jack_client_create_thread(
+            client.raw(),
+            &mut native,
+            priority,
+            realtime as libc::c_int,
+            Some(start_routine),
+            arg as *mut libc::c_void)
+    };
+
+    if ret != 0 {
+        // the thread never started, so the closure is still ours to free
+        drop(unsafe { Box::from_raw(arg) });
+        return Err(status::FAILURE);
+    }
+
+    Ok(RtJoinHandle { native: native, packet: packet, joined: false })
+}
+
+/// Gives the calling thread realtime scheduling at the given priority
+pub fn acquire_real_time_scheduling(priority: i32) -> Result<(), status::Status> {
+    let ret = unsafe {
+        ffi::jack_acquire_real_time_scheduling(libc::pthread_self(), priority)
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(status::FAILURE)
+    }
+}
+
+/// Returns the calling thread to normal scheduling
+pub fn drop_real_time_scheduling() -> Result<(), status::Status> {
+    let ret = unsafe { ffi::jack_drop_real_time_scheduling(libc::pthread_self()) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(status::FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn captures_result_and_panics() {
+        let packet = Arc::new(Mutex::new(None));
+        let arg = Box::into_raw(Box::new(wrap(|| 42, packet.clone())));
+        unsafe { start_routine(arg as *mut libc::c_void) };
+        assert_eq!(packet.lock().unwrap().take().unwrap().unwrap(), 42);
+
+        let packet: Packet<()> = Arc::new(Mutex::new(None));
+        let arg = Box::into_raw(Box::new(wrap(|| panic!("oops"), packet.clone())));
+        unsafe { start_routine(arg as *mut libc::c_void) };
+
+        let payload = packet.lock().unwrap().take().unwrap().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"oops"));
+    }
+}
@@ -0,0 +1,502 @@
+//! This module contains a wrapper around the `jackctl` server control API, which runs a JACK
+//! server inside the current process. It is only available with the `server` feature, which
+//! links against `libjackserver`.
+//!
+//! A server is configured through named parameters, both on the server itself and on each of its
+//! drivers, before it is opened with a driver and started:
+//!
+//! ```no_run
+//! use easyjack::{Client, options};
+//! use easyjack::server::{Server, Value};
+//!
+//! let mut server = Server::new().unwrap();
+//! server.set("name", Value::String("test".to_string())).unwrap();
+//! {
+//!     let dummy = server.driver("dummy").unwrap();
+//!     dummy.set("rate", Value::UInt(48000)).unwrap();
+//!     dummy.set("period", Value::UInt(256)).unwrap();
+//! }
+//!
+//! server.open("dummy").unwrap();
+//! server.start().unwrap();
+//!
+//! let (client, _) = Client::open_connection_to("client", "test", options::NO_START_SERVER)
+//!     .unwrap();
+//! // ...
+//! ```
+//!
+//! `Server::dummy` does all of the above (up to opening the client) in one call. The server is
+//! stopped and closed when it is dropped.
+//!
+//! `libjackserver` also contains the client API, so a process using this module should avoid
+//! linking a second JACK library which could provide different copies of it.
+
+use jack_sys;
+use libc;
+
+use std::ffi::CStr;
+use std::marker::PhantomData;
+
+use types::*;
+
+/// The value of a server, driver, or internal client parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i32),
+    UInt(u32),
+    Char(char),
+    String(String),
+    Bool(bool),
+}
+
+/// The type of a parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Int,
+    UInt,
+    Char,
+    String,
+    Bool,
+}
+
+impl Value {
+    /// Returns the type of the value
+    pub fn value_type(&self) -> ValueType {
+        match *self {
+            Value::Int(_)    => ValueType::Int,
+            Value::UInt(_)   => ValueType::UInt,
+            Value::Char(_)   => ValueType::Char,
+            Value::String(_) => ValueType::String,
+            Value::Bool(_)   => ValueType::Bool,
+        }
+    }
+}
+
+impl ValueType {
+    fn from_raw(t: jack_sys::jackctl_param_type_t) -> Option<Self> {
+        match t {
+            jack_sys::JackParamInt    => Some(ValueType::Int),
+            jack_sys::JackParamUInt   => Some(ValueType::UInt),
+            jack_sys::JackParamChar   => Some(ValueType::Char),
+            jack_sys::JackParamString => Some(ValueType::String),
+            jack_sys::JackParamBool   => Some(ValueType::Bool),
+            _                         => None,
+        }
+    }
+}
+
+// the size of the string member of jackctl_parameter_value, including the terminator
+const MAX_STRING_LEN: usize = 128;
+
+fn from_raw_value(t: ValueType, mut raw: jack_sys::Union_jackctl_parameter_value) -> Value {
+    unsafe {
+        match t {
+            ValueType::Int    => Value::Int(*raw.i()),
+            ValueType::UInt   => Value::UInt(*raw.ui()),
+            ValueType::Char   => Value::Char(*raw.c() as u8 as char),
+            ValueType::Bool   => Value::Bool(*raw.b() != 0),
+            ValueType::String => {
+                let bytes = &*raw.str();
+                let len = bytes.iter().position(|&c| c == 0).unwrap_or(MAX_STRING_LEN);
+                let bytes: Vec<u8> = bytes[..len].iter().map(|&c| c as u8).collect();
+                Value::String(String::from_utf8_lossy(&bytes).into_owned())
+            },
+        }
+    }
+}
+
+/// Returns None if the value can not be represented (a non-ascii char, or a string which is too
+/// long or contains a nul)
+fn to_raw_value(value: &Value) -> Option<jack_sys::Union_jackctl_parameter_value> {
+    let mut raw = jack_sys::Union_jackctl_parameter_value::default();
+    unsafe {
+        match *value {
+            Value::Int(i)  => *raw.i() = i,
+            Value::UInt(u) => *raw.ui() = u,
+            Value::Bool(b) => *raw.b() = b as u8,
+            Value::Char(c) => {
+                if !c.is_ascii() {
+                    return None;
+                }
+
+                *raw.c() = c as libc::c_char;
+            },
+            Value::String(ref s) => {
+                if s.len() >= MAX_STRING_LEN || s.bytes().any(|b| b == 0) {
+                    return None;
+                }
+
+                let dst = &mut *raw.str();
+                for (d, b) in dst.iter_mut().zip(s.bytes()) {
+                    *d = b as libc::c_char;
+                }
+            },
+        }
+    }
+
+    Some(raw)
+}
+
+fn check(ok: u8) -> Result<(), status::Status> {
+    if ok != 0 { Ok(()) } else { Err(status::FAILURE) }
+}
+
+// collects the data pointers out of a (server owned) JSList
+unsafe fn list<T>(mut node: *const jack_sys::JSList) -> Vec<*mut T> {
+    let mut items = Vec::new();
+    while !node.is_null() {
+        items.push((*node).data as *mut T);
+        node = (*node).next;
+    }
+
+    items
+}
+
+unsafe fn string(s: *const libc::c_char) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed,
+    Open,
+    Started,
+}
+
+/// A JACK server running inside this process
+pub struct Server {
+    raw:   *mut jack_sys::jackctl_server_t,
+    state: State,
+}
+
+impl Server {
+    /// Creates a server, with every parameter set to its default. The server does not run until
+    /// it is opened with a driver and started.
+    pub fn new() -> Result<Self, status::Status> {
+        let raw = unsafe { jack_sys::jackctl_server_create(None, None) };
+        if raw.is_null() {
+            Err(status::FAILURE)
+        } else {
+            Ok(Server { raw: raw, state: State::Closed })
+        }
+    }
+
+    /// Creates and starts a server named `name`, running the dummy driver (which needs no audio
+    /// hardware) at the given sample rate and period size
+    pub fn dummy(name: &str, rate: u32, period: NumFrames) -> Result<Self, status::Status> {
+        let mut server = Server::new()?;
+        server.set("name", Value::String(name.to_string()))?;
+        {
+            let driver = server.driver("dummy").ok_or(status::FAILURE)?;
+            driver.set("rate", Value::UInt(rate))?;
+            driver.set("period", Value::UInt(period))?;
+        }
+
+        server.open("dummy")?;
+        server.start()?;
+        Ok(server)
+    }
+
+    /// Returns all of the drivers the server can be opened with
+    pub fn drivers(&self) -> Vec<Driver<'_>> {
+        let raw = unsafe { list(jack_sys::jackctl_server_get_drivers_list(self.raw)) };
+        raw.into_iter().map(|d| Driver { raw: d, server: PhantomData }).collect()
+    }
+
+    /// Returns the driver with the given name, if there is one
+    pub fn driver(&self, name: &str) -> Option<Driver<'_>> {
+        self.drivers().into_iter().find(|d| d.name() == name)
+    }
+
+    /// Returns all of the server's parameters
+    pub fn parameters(&self) -> Vec<Parameter<'_>> {
+        unsafe { Parameter::list(jack_sys::jackctl_server_get_parameters(self.raw)) }
+    }
+
+    /// Returns the server parameter with the given name, if there is one
+    pub fn parameter(&self, name: &str) -> Option<Parameter<'_>> {
+        self.parameters().into_iter().find(|p| p.name() == name)
+    }
+
+    /// Sets the server parameter with the given name. Returns `INVALID_OPTION` if there is no
+    /// such parameter or the value has the wrong type.
+    pub fn set(&self, name: &str, value: Value) -> Result<(), status::Status> {
+        self.parameter(name).ok_or(status::INVALID_OPTION)?.set(value)
+    }
+
+    /// Returns all of the internal clients the server can load
+    pub fn internals(&self) -> Vec<Internal<'_>> {
+        let raw = unsafe { list(jack_sys::jackctl_server_get_internals_list(self.raw)) };
+        raw.into_iter().map(|i| Internal { raw: i, server: PhantomData }).collect()
+    }
+
+    /// Returns the internal client with the given name, if there is one
+    pub fn internal(&self, name: &str) -> Option<Internal<'_>> {
+        self.internals().into_iter().find(|i| i.name() == name)
+    }
+
+    /// Opens the server with the named driver. The driver's parameters should be set before
+    /// calling this.
+    pub fn open(&mut self, driver: &str) -> Result<(), status::Status> {
+        if self.state != State::Closed {
+            return Err(status::FAILURE);
+        }
+
+        let driver = self.driver(driver).ok_or(status::INVALID_OPTION)?.raw;
+        check(unsafe { jack_sys::jackctl_server_open(self.raw, driver) })?;
+        self.state = State::Open;
+        Ok(())
+    }
+
+    /// Starts an open server. Clients can connect to it once this returns.
+    pub fn start(&mut self) -> Result<(), status::Status> {
+        if self.state != State::Open {
+            return Err(status::FAILURE);
+        }
+
+        check(unsafe { jack_sys::jackctl_server_start(self.raw) })?;
+        self.state = State::Started;
+        Ok(())
+    }
+
+    /// Stops a started server. It can be started again, or closed.
+    pub fn stop(&mut self) -> Result<(), status::Status> {
+        if self.state != State::Started {
+            return Err(status::FAILURE);
+        }
+
+        check(unsafe { jack_sys::jackctl_server_stop(self.raw) })?;
+        self.state = State::Open;
+        Ok(())
+    }
+
+    /// Closes an open server, stopping it first if needed
+    pub fn close(&mut self) -> Result<(), status::Status> {
+        if self.state == State::Started {
+            self.stop()?;
+        }
+
+        if self.state != State::Open {
+            return Err(status::FAILURE);
+        }
+
+        check(unsafe { jack_sys::jackctl_server_close(self.raw) })?;
+        self.state = State::Closed;
+        Ok(())
+    }
+
+    /// Returns true if the server has been started (and not stopped)
+    pub fn is_started(&self) -> bool {
+        self.state == State::Started
+    }
+
+    /// Loads the named internal client into a started server. Its parameters should be set
+    /// before calling this.
+    pub fn load_internal(&mut self, name: &str) -> Result<(), status::Status> {
+        let internal = self.internal(name).ok_or(status::INVALID_OPTION)?.raw;
+        let ok = unsafe { jack_sys::jackctl_server_load_internal(self.raw, internal) };
+        if ok != 0 { Ok(()) } else { Err(status::LOAD_FAILURE) }
+    }
+
+    /// Unloads an internal client loaded with `load_internal`
+    pub fn unload_internal(&mut self, name: &str) -> Result<(), status::Status> {
+        let internal = self.internal(name).ok_or(status::INVALID_OPTION)?.raw;
+        check(unsafe { jack_sys::jackctl_server_unload_internal(self.raw, internal) })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if self.state != State::Closed {
+            let _ = self.close();
+        }
+
+        unsafe { jack_sys::jackctl_server_destroy(self.raw) };
+    }
+}
+
+/// A backend a `Server` can be opened with, such as `alsa` or `dummy`
+pub struct Driver<'a> {
+    raw:    *mut jack_sys::jackctl_driver_t,
+    server: PhantomData<&'a Server>,
+}
+
+impl<'a> Driver<'a> {
+    /// Returns the name of the driver
+    pub fn name(&self) -> String {
+        unsafe { string(jack_sys::jackctl_driver_get_name(self.raw)) }
+    }
+
+    /// Returns true if this driver can be the server's main driver, false if it can only be
+    /// added as a secondary ("slave") driver
+    pub fn is_master(&self) -> bool {
+        unsafe { jack_sys::jackctl_driver_get_type(self.raw) == jack_sys::JackMaster }
+    }
+
+    /// Returns all of the driver's parameters
+    pub fn parameters(&self) -> Vec<Parameter<'a>> {
+        unsafe { Parameter::list(jack_sys::jackctl_driver_get_parameters(self.raw)) }
+    }
+
+    /// Returns the driver parameter with the given name, if there is one
+    pub fn parameter(&self, name: &str) -> Option<Parameter<'a>> {
+        self.parameters().into_iter().find(|p| p.name() == name)
+    }
+
+    /// Sets the driver parameter with the given name. Returns `INVALID_OPTION` if there is no
+    /// such parameter or the value has the wrong type.
+    pub fn set(&self, name: &str, value: Value) -> Result<(), status::Status> {
+        self.parameter(name).ok_or(status::INVALID_OPTION)?.set(value)
+    }
+}
+
+/// An internal client a `Server` can load, such as `netmanager`
+pub struct Internal<'a> {
+    raw:    *mut jack_sys::jackctl_internal_t,
+    server: PhantomData<&'a Server>,
+}
+
+impl<'a> Internal<'a> {
+    /// Returns the name of the internal client
+    pub fn name(&self) -> String {
+        unsafe { string(jack_sys::jackctl_internal_get_name(self.raw)) }
+    }
+
+    /// Returns all of the internal client's parameters
+    pub fn parameters(&self) -> Vec<Parameter<'a>> {
+        unsafe { Parameter::list(jack_sys::jackctl_internal_get_parameters(self.raw)) }
+    }
+
+    /// Returns the parameter with the given name, if there is one
+    pub fn parameter(&self, name: &str) -> Option<Parameter<'a>> {
+        self.parameters().into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// A named, typed setting of a server, driver, or internal client
+pub struct Parameter<'a> {
+    raw:    *mut jack_sys::jackctl_parameter_t,
+    server: PhantomData<&'a Server>,
+}
+
+impl<'a> Parameter<'a> {
+    unsafe fn list(l: *const jack_sys::JSList) -> Vec<Self> {
+        list(l).into_iter().map(|p| Parameter { raw: p, server: PhantomData }).collect()
+    }
+
+    /// Returns the name of the parameter
+    pub fn name(&self) -> String {
+        unsafe { string(jack_sys::jackctl_parameter_get_name(self.raw)) }
+    }
+
+    /// Returns the single character used for this parameter on the `jackd` command line
+    pub fn id(&self) -> char {
+        unsafe { jack_sys::jackctl_parameter_get_id(self.raw) as u8 as char }
+    }
+
+    /// Returns a one line description of the parameter
+    pub fn short_description(&self) -> String {
+        unsafe { string(jack_sys::jackctl_parameter_get_short_description(self.raw)) }
+    }
+
+    /// Returns a longer description of the parameter
+    pub fn long_description(&self) -> String {
+        unsafe { string(jack_sys::jackctl_parameter_get_long_description(self.raw)) }
+    }
+
+    /// Returns the type of the parameter's value
+    pub fn value_type(&self) -> ValueType {
+        let t = unsafe { jack_sys::jackctl_parameter_get_type(self.raw) };
+        ValueType::from_raw(t).expect("jack returned an unknown parameter type")
+    }
+
+    /// Returns true if the parameter has been set (rather than left at its default)
+    pub fn is_set(&self) -> bool {
+        unsafe { jack_sys::jackctl_parameter_is_set(self.raw) != 0 }
+    }
+
+    /// Returns the current value of the parameter
+    pub fn value(&self) -> Value {
+        let raw = unsafe { jack_sys::jackctl_parameter_get_value(self.raw) };
+        from_raw_value(self.value_type(), raw)
+    }
+
+    /// Returns the default value of the parameter
+    pub fn default_value(&self) -> Value {
+        let raw = unsafe { jack_sys::jackctl_parameter_get_default_value(self.raw) };
+        from_raw_value(self.value_type(), raw)
+    }
+
+    /// Returns the values the parameter is restricted to, with their descriptions. Returns an
+    /// empty list if the parameter is not restricted to a set of values.
+    pub fn choices(&self) -> Vec<(Value, String)> {
+        let t = self.value_type();
+        unsafe {
+            if jack_sys::jackctl_parameter_has_enum_constraint(self.raw) == 0 {
+                return Vec::new();
+            }
+
+            let count = jack_sys::jackctl_parameter_get_enum_constraints_count(self.raw);
+            (0..count).map(|i| {
+                let v = jack_sys::jackctl_parameter_get_enum_constraint_value(self.raw, i);
+                let d = jack_sys::jackctl_parameter_get_enum_constraint_description(self.raw, i);
+                (from_raw_value(t, v), string(d))
+            }).collect()
+        }
+    }
+
+    /// Sets the parameter. Returns `INVALID_OPTION` if the value has the wrong type or can not be
+    /// represented (strings are limited to 127 bytes and chars to ascii), and `FAILURE` if JACK
+    /// rejects it.
+    pub fn set(&self, value: Value) -> Result<(), status::Status> {
+        if value.value_type() != self.value_type() {
+            return Err(status::INVALID_OPTION);
+        }
+
+        let raw = to_raw_value(&value).ok_or(status::INVALID_OPTION)?;
+        check(unsafe { jack_sys::jackctl_parameter_set_value(self.raw, &raw) })
+    }
+
+    /// Resets the parameter to its default value
+    pub fn reset(&self) -> Result<(), status::Status> {
+        check(unsafe { jack_sys::jackctl_parameter_reset(self.raw) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(v: Value) -> Value {
+        from_raw_value(v.value_type(), to_raw_value(&v).unwrap())
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let values = vec![
+            Value::Int(-3),
+            Value::UInt(48000),
+            Value::Char('d'),
+            Value::Bool(true),
+            Value::Bool(false),
+            Value::String("".to_string()),
+            Value::String("server".to_string()),
+            Value::String("x".repeat(MAX_STRING_LEN - 1)),
+        ];
+
+        for v in values {
+            assert_eq!(round_trip(v.clone()), v);
+        }
+    }
+
+    #[test]
+    fn unrepresentable_values() {
+        assert!(to_raw_value(&Value::Char('é')).is_none());
+        assert!(to_raw_value(&Value::String("x".repeat(MAX_STRING_LEN))).is_none());
+        assert!(to_raw_value(&Value::String("a\0b".to_string())).is_none());
+    }
+}
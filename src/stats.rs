@@ -0,0 +1,233 @@
+use ffi;
+use jack_sys;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use types::*;
+
+/// The number of buckets in the process time histogram. See `ClientStats::histogram`.
+pub const HISTOGRAM_BUCKETS: usize = 24;
+
+// the counters backing `ClientStats`. Everything except `timing` is only written by one thread at
+// a time (the process thread for the timing fields, the jack notification thread for the xrun
+// fields), so plain loads and stores are enough
+pub(crate) struct StatsCollector {
+    client:     AtomicPtr<jack_sys::jack_client_t>,
+    timing:     AtomicBool,
+
+    cycles:     AtomicU64,
+    total_ns:   AtomicU64,
+    min_ns:     AtomicU64,
+    max_ns:     AtomicU64,
+    last_ns:    AtomicU64,
+    histogram:  [AtomicU64; HISTOGRAM_BUCKETS],
+
+    // f32 bits
+    last_load:  AtomicU32,
+    max_load:   AtomicU32,
+
+    xruns:           AtomicU64,
+    last_xrun_delay: AtomicU32,
+    max_xrun_delay:  AtomicU32,
+}
+
+impl StatsCollector {
+    pub fn new(client: *mut jack_sys::jack_client_t) -> Self {
+        StatsCollector {
+            client:          AtomicPtr::new(client),
+            timing:          AtomicBool::new(false),
+            cycles:          AtomicU64::new(0),
+            total_ns:        AtomicU64::new(0),
+            min_ns:          AtomicU64::new(u64::max_value()),
+            max_ns:          AtomicU64::new(0),
+            last_ns:         AtomicU64::new(0),
+            histogram:       Default::default(),
+            last_load:       AtomicU32::new(0),
+            max_load:        AtomicU32::new(0),
+            xruns:           AtomicU64::new(0),
+            last_xrun_delay: AtomicU32::new(0),
+            max_xrun_delay:  AtomicU32::new(0),
+        }
+    }
+
+    pub fn timing_enabled(&self) -> bool {
+        self.timing.load(Ordering::Relaxed)
+    }
+
+    pub fn set_timing(&self, enabled: bool) {
+        self.timing.store(enabled, Ordering::Relaxed)
+    }
+
+    /// Records one run of the process handler which took `elapsed` to process `nframes` frames
+    pub fn record_cycle(&self, elapsed: Duration, nframes: NumFrames, srate: NumFrames) {
+        let ns = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+
+        self.cycles.store(self.cycles.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        self.total_ns.store(self.total_ns.load(Ordering::Relaxed) + ns, Ordering::Relaxed);
+        self.last_ns.store(ns, Ordering::Relaxed);
+
+        if ns < self.min_ns.load(Ordering::Relaxed) {
+            self.min_ns.store(ns, Ordering::Relaxed);
+        }
+
+        if ns > self.max_ns.load(Ordering::Relaxed) {
+            self.max_ns.store(ns, Ordering::Relaxed);
+        }
+
+        let h = &self.histogram[bucket_for(ns / 1000)];
+        h.store(h.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+
+        if nframes > 0 && srate > 0 {
+            let period_ns = nframes as f64 * 1e9 / srate as f64;
+            let load = (ns as f64 / period_ns) as f32;
+
+            self.last_load.store(load.to_bits(), Ordering::Relaxed);
+            if load > f32::from_bits(self.max_load.load(Ordering::Relaxed)) {
+                self.max_load.store(load.to_bits(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records an xrun, which was reported `delay` microseconds late
+    pub fn record_xrun(&self, delay: f32) {
+        self.xruns.store(self.xruns.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        self.last_xrun_delay.store(delay.to_bits(), Ordering::Relaxed);
+        if delay > f32::from_bits(self.max_xrun_delay.load(Ordering::Relaxed)) {
+            self.max_xrun_delay.store(delay.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Records an xrun for the client, asking jack how late it was
+    pub fn record_client_xrun(&self) {
+        let client = self.client.load(Ordering::Relaxed);
+        self.record_xrun(unsafe { ffi::jack_get_xrun_delayed_usecs(client) });
+    }
+
+    pub fn snapshot(&self) -> ClientStats {
+        let cycles = self.cycles.load(Ordering::Relaxed);
+        let total = self.total_ns.load(Ordering::Relaxed);
+        let min = if cycles == 0 { 0 } else { self.min_ns.load(Ordering::Relaxed) };
+
+        let mut histogram = [0; HISTOGRAM_BUCKETS];
+        for (h, c) in histogram.iter_mut().zip(self.histogram.iter()) {
+            *h = c.load(Ordering::Relaxed);
+        }
+
+        ClientStats {
+            cycles:          cycles,
+            min:             Duration::from_nanos(min),
+            mean:            Duration::from_nanos(if cycles == 0 { 0 } else { total / cycles }),
+            max:             Duration::from_nanos(self.max_ns.load(Ordering::Relaxed)),
+            last:            Duration::from_nanos(self.last_ns.load(Ordering::Relaxed)),
+            histogram:       histogram,
+            last_load:       f32::from_bits(self.last_load.load(Ordering::Relaxed)),
+            max_load:        f32::from_bits(self.max_load.load(Ordering::Relaxed)),
+            xruns:           self.xruns.load(Ordering::Relaxed),
+            last_xrun_delay: f32::from_bits(self.last_xrun_delay.load(Ordering::Relaxed)),
+            max_xrun_delay:  f32::from_bits(self.max_xrun_delay.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// bucket 0 holds times under 1µs, bucket i holds [2^(i-1), 2^i) µs, the last bucket holds
+// everything else
+fn bucket_for(us: u64) -> usize {
+    let b = 64 - us.leading_zeros() as usize;
+    if b >= HISTOGRAM_BUCKETS { HISTOGRAM_BUCKETS - 1 } else { b }
+}
+
+/// A snapshot of the statistics collected for a client.
+///
+/// The process timing fields are only updated while timing is enabled (see
+/// `Client::set_process_timing`); xruns are always counted. Each field is read atomically, but
+/// the snapshot as a whole is not, so fields may come from adjacent cycles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientStats {
+    /// The number of process cycles which have been timed
+    pub cycles: u64,
+
+    /// The shortest, mean, longest, and most recent times spent in the process handler
+    pub min:  Duration,
+    pub mean: Duration,
+    pub max:  Duration,
+    pub last: Duration,
+
+    /// A histogram of the time spent in the process handler. Bucket 0 counts cycles which took
+    /// less than 1µs, bucket `i` counts cycles which took at least `2^(i-1)` and less than `2^i`
+    /// µs, and the last bucket also counts everything longer.
+    pub histogram: [u64; HISTOGRAM_BUCKETS],
+
+    /// The fraction of the period spent in the process handler, for the most recent cycle and the
+    /// worst cycle. A load of 1.0 or more means the handler missed its deadline.
+    pub last_load: f32,
+    pub max_load:  f32,
+
+    /// The number of xruns the server has reported
+    pub xruns: u64,
+
+    /// How late (in microseconds) the most recent xrun and the worst xrun were, as reported by
+    /// `jack_get_xrun_delayed_usecs`
+    pub last_xrun_delay: f32,
+    pub max_xrun_delay:  f32,
+}
+
+/// A handle to a client's statistics which can be sent to another thread (for example, a
+/// monitoring thread). Taking a snapshot never blocks the process thread.
+#[derive(Clone)]
+pub struct StatsHandle {
+    stats: Arc<StatsCollector>,
+}
+
+impl StatsHandle {
+    pub(crate) fn new(stats: Arc<StatsCollector>) -> Self {
+        StatsHandle { stats: stats }
+    }
+
+    /// Returns the current statistics
+    pub fn snapshot(&self) -> ClientStats {
+        self.stats.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        assert_eq!(bucket_for(0), 0);
+        assert_eq!(bucket_for(1), 1);
+        assert_eq!(bucket_for(2), 2);
+        assert_eq!(bucket_for(3), 2);
+        assert_eq!(bucket_for(1000), 10);
+        assert_eq!(bucket_for(u64::max_value()), HISTOGRAM_BUCKETS - 1);
+    }
+
+    #[test]
+    fn snapshot() {
+        let s = StatsCollector::new(::std::ptr::null_mut());
+        assert_eq!(s.snapshot().cycles, 0);
+        assert_eq!(s.snapshot().min, Duration::from_nanos(0));
+
+        // 256 frames at 48kHz is 5333µs
+        s.record_cycle(Duration::from_micros(1000), 256, 48000);
+        s.record_cycle(Duration::from_micros(3000), 256, 48000);
+        s.record_xrun(150.0);
+        s.record_xrun(20.0);
+
+        let snap = s.snapshot();
+        assert_eq!(snap.cycles, 2);
+        assert_eq!(snap.min, Duration::from_micros(1000));
+        assert_eq!(snap.mean, Duration::from_micros(2000));
+        assert_eq!(snap.max, Duration::from_micros(3000));
+        assert_eq!(snap.last, Duration::from_micros(3000));
+        assert_eq!(snap.histogram[10], 1);
+        assert_eq!(snap.histogram[12], 1);
+        assert!((snap.max_load - 0.5625).abs() < 1e-4);
+        assert_eq!(snap.xruns, 2);
+        assert_eq!(snap.last_xrun_delay, 20.0);
+        assert_eq!(snap.max_xrun_delay, 150.0);
+    }
+}
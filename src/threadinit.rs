@@ -0,0 +1,201 @@
+use libc;
+
+use std::mem;
+
+use callbackhandler::*;
+use types::*;
+
+/// Sets the calling thread's floating point unit to flush denormal results to zero and to treat
+/// denormal inputs as zero. Denormals are extremely slow on many CPUs, and are common in decaying
+/// signals (reverb tails, filters fed with silence, etc).
+///
+/// On x86 this sets the FTZ and DAZ bits of MXCSR, on aarch64 it sets the FZ bit of FPCR. Returns
+/// false (and does nothing) on other architectures.
+pub fn flush_denormals() -> bool {
+    flush_denormals_impl()
+}
+
+#[cfg(any(target_arch = "x86_64", all(target_arch = "x86", target_feature = "sse")))]
+fn flush_denormals_impl() -> bool {
+    use std::arch::asm;
+
+    // bit 15 is flush to zero, bit 6 is denormals are zero
+    const FTZ_DAZ: u32 = 0x8040;
+
+    let mut csr: u32 = 0;
+    unsafe {
+        asm!("stmxcsr [{}]", in(reg) &mut csr, options(nostack));
+        csr |= FTZ_DAZ;
+        asm!("ldmxcsr [{}]", in(reg) &csr, options(nostack));
+    }
+
+    true
+}
+
+#[cfg(target_arch = "aarch64")]
+fn flush_denormals_impl() -> bool {
+    use std::arch::asm;
+
+    // bit 24 is flush to zero, which covers both inputs and outputs
+    const FZ: u64 = 1 << 24;
+
+    unsafe {
+        let mut fpcr: u64;
+        asm!("mrs {}, fpcr", out(reg) fpcr, options(nomem, nostack));
+        fpcr |= FZ;
+        asm!("msr fpcr, {}", in(reg) fpcr, options(nomem, nostack));
+    }
+
+    true
+}
+
+#[cfg(not(any(target_arch = "x86_64",
+              all(target_arch = "x86", target_feature = "sse"),
+              target_arch = "aarch64")))]
+fn flush_denormals_impl() -> bool {
+    false
+}
+
+/// Restricts the calling thread to run only on the given CPU core.
+///
+/// Returns `INVALID_OPTION` if the core number is out of range and `FAILURE` if the kernel
+/// refuses (for example, if the core is not available to this process). Only supported on Linux;
+/// returns `FAILURE` elsewhere.
+pub fn pin_to_core(core: usize) -> Result<(), status::Status> {
+    pin_to_core_impl(core)
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_core_impl(core: usize) -> Result<(), status::Status> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(status::INVALID_OPTION);
+    }
+
+    let ret = unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(status::FAILURE)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core_impl(_core: usize) -> Result<(), status::Status> {
+    Err(status::FAILURE)
+}
+
+/// A `ThreadInitHandler` which runs some common setup steps, followed by an optional closure.
+/// None of the steps are enabled by default.
+///
+/// ```no_run
+/// use easyjack::{Client, ThreadSetup};
+/// use std::sync::atomic::{AtomicBool, Ordering};
+///
+/// static STARTED: AtomicBool = AtomicBool::new(false);
+///
+/// # fn f(client: &mut Client) {
+/// let setup = ThreadSetup::new()
+///     .flush_denormals()
+///     .pin_to_core(2)
+///     .then(|| STARTED.store(true, Ordering::Release));
+///
+/// client.set_thread_init_handler(setup).unwrap();
+/// # }
+/// ```
+///
+/// Failures are ignored, since there is nobody to report them to on the new thread. Use the
+/// free functions `flush_denormals` and `pin_to_core` from a closure to handle them.
+pub struct ThreadSetup<'a> {
+    flush_denormals: bool,
+    core:            Option<usize>,
+    then:            Option<Box<dyn FnMut() + 'a>>,
+}
+
+impl<'a> ThreadSetup<'a> {
+    pub fn new() -> Self {
+        ThreadSetup {
+            flush_denormals: false,
+            core:            None,
+            then:            None,
+        }
+    }
+
+    /// Enables flushing denormals to zero on the thread. See `flush_denormals`.
+    pub fn flush_denormals(mut self) -> Self {
+        self.flush_denormals = true;
+        self
+    }
+
+    /// Pins the thread to the given core. See `pin_to_core`.
+    pub fn pin_to_core(mut self, core: usize) -> Self {
+        self.core = Some(core);
+        self
+    }
+
+    /// Runs `f` after the other setup steps. `f` runs once on the process thread, before its first
+    /// cycle, so this is the place for per-thread allocation (thread-local buffers and the like).
+    pub fn then<F: FnMut() + 'a>(mut self, f: F) -> Self {
+        self.then = Some(Box::new(f));
+        self
+    }
+}
+
+impl<'a> Default for ThreadSetup<'a> {
+    fn default() -> Self { ThreadSetup::new() }
+}
+
+impl<'a> ThreadInitHandler for ThreadSetup<'a> {
+    fn thread_init(&mut self) {
+        if self.flush_denormals {
+            flush_denormals();
+        }
+
+        if let Some(core) = self.core {
+            let _ = pin_to_core(core);
+        }
+
+        if let Some(ref mut f) = self.then {
+            f();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::hint::black_box;
+    use std::thread;
+
+    #[test]
+    fn setup_runs_on_thread() {
+        let smallest_normal = black_box(::std::f32::MIN_POSITIVE);
+
+        let (denormal, ran) = thread::spawn(move || {
+            let mut ran = false;
+            {
+                let mut setup = ThreadSetup::new().flush_denormals().then(|| ran = true);
+                setup.thread_init();
+            }
+
+            (black_box(smallest_normal) / black_box(2.0), ran)
+        }).join().unwrap();
+
+        assert!(ran);
+        if cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
+            assert_eq!(denormal, 0.0);
+        }
+
+        // denormals are untouched on this thread
+        assert!(black_box(smallest_normal) / black_box(2.0) > 0.0);
+    }
+
+    #[test]
+    fn pin_out_of_range() {
+        assert!(pin_to_core(usize::max_value()).is_err());
+    }
+}
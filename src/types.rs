@@ -13,6 +13,50 @@ pub enum PortConnectStatus {
     PortsDisconnected,
 }
 
+/// The direction data flows through a port, from the point of view of the client which owns it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortDirection {
+    Input,
+    Output,
+}
+
+/// The direction of the signal flow a latency value refers to.
+///
+/// Capture latency is the time it took for data to get from the edge of the graph (for example, a
+/// physical input) to a port. Playback latency is the time it will take for data to get from a port
+/// to the edge of the graph (for example, a physical output).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyMode {
+    Capture,
+    Playback,
+}
+
+impl LatencyMode {
+    #[doc(hidden)]
+    pub fn from_raw(mode: jack_sys::jack_latency_callback_mode_t) -> Self {
+        if mode == jack_sys::JackCaptureLatency {
+            LatencyMode::Capture
+        } else {
+            LatencyMode::Playback
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn to_raw(self) -> jack_sys::jack_latency_callback_mode_t {
+        match self {
+            LatencyMode::Capture  => jack_sys::JackCaptureLatency,
+            LatencyMode::Playback => jack_sys::JackPlaybackLatency,
+        }
+    }
+}
+
+/// A range of latencies, measured in frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyRange {
+    pub min: NumFrames,
+    pub max: NumFrames,
+}
+
 /// This module contains constants and a bitflags! generated struct mapping to the jack port flags
 /// bitset for specifying options on jack ports
 ///
@@ -113,7 +157,12 @@ pub mod status {
         const BACKEND_ERROR = jack_sys::JackBackendError,
 
         /// Client zombified failure
-        const CLIENT_ZOMBIE = jack_sys::JackClientZombie
+        const CLIENT_ZOMBIE = jack_sys::JackClientZombie,
+
+        /// libjack could not be loaded, so JACK was never reached. Only reported with the `dlopen`
+        /// feature; `load_library` gives the reason. This one is easyjack's own, JACK does not use
+        /// this bit.
+        const LIBRARY_NOT_FOUND = 0x10000
     }
 }
 }
@@ -141,9 +190,16 @@ pub mod options {
         #[doc(hidden)]
         const SERVER_NAME = jack_sys::JackServerName,
 
-        // TODO figure these out
-        // const LOAD_NAME = jack_sys::JackLoadName,
-        // const LOAD_INIT = jack_sys::JackLoadInit,
+        /// Load the internal client from the shared object with the given name, instead of the
+        /// one named after the client. `Client::load_internal_client` sets this when a load name
+        /// is given.
+        const LOAD_NAME = jack_sys::JackLoadName,
+
+        /// Pass an initialization string to the internal client. `Client::load_internal_client`
+        /// sets this when an init string is given.
+        const LOAD_INIT = jack_sys::JackLoadInit,
+
+        // TODO figure this out
         // const SESSION_ID = jack_sys::JackSessionID,
     }
 }
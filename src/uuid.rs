@@ -0,0 +1,45 @@
+use types::*;
+
+/// The value JACK uses to represent an empty (unset) UUID
+pub const UUID_EMPTY: UUID = 0;
+
+/// Parses the string representation of a UUID, as returned by the JACK client UUID functions.
+///
+/// Returns None if the string is not a valid UUID. The empty UUID is not considered valid.
+pub fn uuid_parse(s: &str) -> Option<UUID> {
+    match s.trim().parse::<UUID>() {
+        Ok(UUID_EMPTY) | Err(_) => None,
+        Ok(uuid)                => Some(uuid),
+    }
+}
+
+/// Formats a UUID as a string, in the same format that the JACK C API uses.
+pub fn uuid_unparse(uuid: UUID) -> String {
+    uuid.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_valid() {
+        assert_eq!(uuid_parse("4294967298"), Some(4294967298));
+        assert_eq!(uuid_parse(" 12\n"), Some(12));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(uuid_parse(""), None);
+        assert_eq!(uuid_parse("0"), None);
+        assert_eq!(uuid_parse("-3"), None);
+        assert_eq!(uuid_parse("not a uuid"), None);
+        assert_eq!(uuid_parse("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let uuid = 0x1_0000_0003;
+        assert_eq!(uuid_parse(&uuid_unparse(uuid)), Some(uuid));
+    }
+}
@@ -0,0 +1,202 @@
+use ffi;
+use jack_sys;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use callbackhandler::*;
+use ring::*;
+use types::*;
+
+// the number of overruns which can be waiting for the watchdog thread
+const QUEUE_CAPACITY: usize = 64;
+
+// how often the watchdog thread checks for new overruns
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A cycle in which the process handler used more of the period than the watchdog allows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overrun {
+    /// The frame time at the start of the cycle (see `jack_last_frame_time`). This can be used to
+    /// line the overrun up with other events.
+    pub frame_time: NumFrames,
+
+    /// The number of frames of time which had passed since the start of the cycle when the handler
+    /// returned
+    pub used: NumFrames,
+
+    /// The period size of the cycle
+    pub nframes: NumFrames,
+}
+
+impl Overrun {
+    /// Returns the fraction of the period which was used
+    pub fn fraction(&self) -> f32 {
+        self.used as f32 / self.nframes as f32
+    }
+}
+
+/// Wraps a `ProcessHandler` and checks how much of the period it used at the end of every cycle.
+///
+/// Cycles which used more than the configured fraction of the period are passed to a callback on
+/// a separate, non-realtime thread (see `Client::watchdog`). The check itself never blocks or
+/// allocates. If the watchdog thread falls behind, overruns are dropped and counted instead.
+pub struct Watchdog<H> {
+    inner:     H,
+    client:    *const jack_sys::jack_client_t,
+    threshold: f32,
+    queue:     Producer<Overrun>,
+    dropped:   Arc<AtomicUsize>,
+}
+
+impl<H: ProcessHandler> Watchdog<H> {
+    #[doc(hidden)]
+    pub fn new<F>(
+        inner: H,
+        client: *const jack_sys::jack_client_t,
+        threshold: f32,
+        on_overrun: F)
+        -> (Self, WatchdogThread)
+        where F: FnMut(Overrun) + Send + 'static
+    {
+        let (p, c) = ring(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let wd = Watchdog {
+            inner:     inner,
+            client:    client,
+            threshold: threshold,
+            queue:     p,
+            dropped:   dropped.clone(),
+        };
+
+        (wd, WatchdogThread::spawn(c, dropped, on_overrun))
+    }
+
+    /// Returns a reference to the inner handler
+    pub fn inner(&self) -> &H { &self.inner }
+
+    /// Returns a mutable reference to the inner handler
+    pub fn inner_mut(&mut self) -> &mut H { &mut self.inner }
+
+    // queues an overrun if `used` frames is too much of the period
+    fn check(&mut self, used: NumFrames, nframes: NumFrames, frame_time: NumFrames) {
+        if nframes == 0 || (used as f32) <= self.threshold * nframes as f32 {
+            return;
+        }
+
+        let o = Overrun {
+            frame_time: frame_time,
+            used:       used,
+            nframes:    nframes,
+        };
+
+        if self.queue.push(o).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<H: ProcessHandler> ProcessHandler for Watchdog<H> {
+    fn process(&mut self, ctx: &CallbackContext, nframes: NumFrames) -> i32 {
+        let ret = self.inner.process(ctx, nframes);
+
+        let (used, frame_time) = unsafe {
+            (ffi::jack_frames_since_cycle_start(self.client),
+             ffi::jack_last_frame_time(self.client))
+        };
+
+        self.check(used, nframes, frame_time);
+        ret
+    }
+}
+
+/// The thread which delivers overruns detected by a `Watchdog`. Stops the thread when dropped.
+pub struct WatchdogThread {
+    running: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+    handle:  Option<thread::JoinHandle<()>>,
+}
+
+impl WatchdogThread {
+    fn spawn<F>(mut queue: Consumer<Overrun>, dropped: Arc<AtomicUsize>, mut on_overrun: F) -> Self
+        where F: FnMut(Overrun) + Send + 'static
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+
+        let handle = thread::spawn(move || {
+            loop {
+                let stop = !r.load(Ordering::SeqCst);
+                while let Some(o) = queue.pop() {
+                    on_overrun(o);
+                }
+
+                if stop {
+                    break;
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+
+        WatchdogThread {
+            running: running,
+            dropped: dropped,
+            handle:  Some(handle),
+        }
+    }
+
+    /// Returns the number of overruns which were dropped because the thread fell behind
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for WatchdogThread {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+    use std::sync::mpsc;
+
+    struct Nothing;
+
+    impl ProcessHandler for Nothing {
+        fn process(&mut self, _: &CallbackContext, _: NumFrames) -> i32 { 0 }
+    }
+
+    #[test]
+    fn reports_overruns() {
+        let (tx, rx) = mpsc::channel();
+        let (mut wd, thread) = Watchdog::new(Nothing, ptr::null(), 0.5, move |o| {
+            tx.send(o).unwrap();
+        });
+
+        wd.check(100, 256, 1000);
+        wd.check(128, 256, 1256);
+        wd.check(200, 256, 1512);
+
+        for i in 0..QUEUE_CAPACITY + 3 {
+            wd.check(256, 256, i as NumFrames);
+        }
+
+        let dropped = wd.dropped.load(Ordering::Relaxed);
+        drop(thread);
+
+        let got: Vec<Overrun> = rx.iter().collect();
+        assert_eq!(got[0], Overrun { frame_time: 1512, used: 200, nframes: 256 });
+        assert_eq!(got[0].fraction(), 200.0 / 256.0);
+        assert_eq!(got.len() + dropped, QUEUE_CAPACITY + 4);
+    }
+}
@@ -0,0 +1,371 @@
+//! The libjack functions, looked up with `dlopen` the first time one is called instead of being
+//! linked at build time (the `dlopen` feature). Programs built this way start on machines without
+//! JACK installed, and only find out it is missing when they try to use it. This is the same
+//! approach JACK2's "weakjack" takes.
+//!
+//! Each function has the same signature as in jack_sys, so `ffi` can re-export these instead. If
+//! libjack (or a function missing from an older libjack) can not be found, the function returns
+//! the value JACK uses to report a failure instead.
+
+use jack_sys::*;
+use libc;
+
+use std::ffi::{CStr, CString};
+use std::mem;
+use std::ptr;
+use std::sync::OnceLock;
+
+// the names to try, in order
+#[cfg(target_os = "macos")]
+const LIBRARY_NAMES: &[&str] = &["libjack.0.dylib", "libjack.dylib"];
+
+#[cfg(not(target_os = "macos"))]
+const LIBRARY_NAMES: &[&str] = &["libjack.so.0", "libjack.so"];
+
+// the loaded functions, or the loader's explanation of why libjack could not be loaded
+static LIBRARY: OnceLock<Result<Symbols, String>> = OnceLock::new();
+
+// the variadic functions, which are declared by hand
+type ClientOpen = unsafe extern "C" fn(
+    *const libc::c_char, jack_options_t, *mut jack_status_t, ...) -> *mut jack_client_t;
+
+type InternalClientLoad = unsafe extern "C" fn(
+    *mut jack_client_t, *const libc::c_char, jack_options_t, *mut jack_status_t, ...)
+    -> jack_intclient_t;
+
+/// Loads libjack, if that has not been tried yet. Returns why it could not be loaded, if it could
+/// not.
+pub(crate) fn library() -> Result<(), String> {
+    match *LIBRARY.get_or_init(open) {
+        Ok(_)        => Ok(()),
+        Err(ref why) => Err(why.clone()),
+    }
+}
+
+fn symbols() -> Option<&'static Symbols> {
+    LIBRARY.get_or_init(open).as_ref().ok()
+}
+
+fn open() -> Result<Symbols, String> {
+    let mut why = String::new();
+    for name in LIBRARY_NAMES {
+        let cname = CString::new(*name).unwrap();
+        unsafe {
+            // never closed, the functions are used for the rest of the program
+            let handle = libc::dlopen(cname.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if !handle.is_null() {
+                return Ok(Symbols::load(handle));
+            }
+
+            let err = libc::dlerror();
+            if !err.is_null() {
+                why = CStr::from_ptr(err).to_string_lossy().into_owned();
+            }
+        }
+    }
+
+    Err(why)
+}
+
+unsafe fn symbol(handle: *mut libc::c_void, name: &str) -> *mut libc::c_void {
+    let cname = CString::new(name).unwrap();
+    libc::dlsym(handle, cname.as_ptr())
+}
+
+// declares the table of functions, and a wrapper for each which calls the loaded function or
+// returns the fallback value
+macro_rules! weak_functions {
+    ($( fn $name:ident($($arg:ident: $ty:ty),* $(,)*) -> $ret:ty = $fallback:expr; )*) => {
+        struct Symbols {
+            jack_client_open:          Option<ClientOpen>,
+            jack_internal_client_load: Option<InternalClientLoad>,
+            $( $name: Option<unsafe extern "C" fn($($ty),*) -> $ret>, )*
+        }
+
+        impl Symbols {
+            // a missing symbol is a null pointer, which transmutes to None
+            unsafe fn load(handle: *mut libc::c_void) -> Self {
+                Symbols {
+                    jack_client_open:
+                        mem::transmute(symbol(handle, "jack_client_open")),
+                    jack_internal_client_load:
+                        mem::transmute(symbol(handle, "jack_internal_client_load")),
+                    $( $name: mem::transmute(symbol(handle, stringify!($name))), )*
+                }
+            }
+        }
+
+        $(
+            pub unsafe fn $name($($arg: $ty),*) -> $ret {
+                match symbols().and_then(|s| s.$name) {
+                    Some(f) => f($($arg),*),
+                    None    => $fallback,
+                }
+            }
+        )*
+    }
+}
+
+weak_functions! {
+    fn jack_acquire_real_time_scheduling(
+        thread: jack_native_thread_t,
+        priority: libc::c_int,
+    ) -> libc::c_int = -1;
+    fn jack_activate(client: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_client_close(client: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_client_create_thread(
+        client: *mut jack_client_t,
+        thread: *mut jack_native_thread_t,
+        priority: libc::c_int,
+        realtime: libc::c_int,
+        start_routine: Option<unsafe extern "C" fn(*mut libc::c_void) -> *mut libc::c_void>,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_client_get_uuid(client: *mut jack_client_t) -> *mut libc::c_char = ptr::null_mut();
+    fn jack_client_max_real_time_priority(arg1: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_client_real_time_priority(arg1: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_connect(
+        client: *mut jack_client_t,
+        source_port: *const libc::c_char,
+        destination_port: *const libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_cycle_signal(client: *mut jack_client_t, status: libc::c_int) -> () = ();
+    fn jack_cycle_wait(client: *mut jack_client_t) -> jack_nframes_t = 0;
+    fn jack_deactivate(client: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_disconnect(
+        client: *mut jack_client_t,
+        source_port: *const libc::c_char,
+        destination_port: *const libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_drop_real_time_scheduling(thread: jack_native_thread_t) -> libc::c_int = -1;
+    fn jack_frames_since_cycle_start(arg1: *const jack_client_t) -> jack_nframes_t = 0;
+    fn jack_free(ptr: *mut libc::c_void) -> () = ();
+    fn jack_free_description(
+        desc: *mut jack_description_t,
+        free_description_itself: libc::c_int,
+    ) -> () = ();
+    fn jack_get_all_properties(descs: *mut *mut jack_description_t) -> libc::c_int = -1;
+    fn jack_get_buffer_size(arg1: *mut jack_client_t) -> jack_nframes_t = 0;
+    fn jack_get_client_name(client: *mut jack_client_t) -> *mut libc::c_char = ptr::null_mut();
+    fn jack_get_client_name_by_uuid(
+        client: *mut jack_client_t,
+        client_uuid: *const libc::c_char,
+    ) -> *mut libc::c_char = ptr::null_mut();
+    fn jack_get_internal_client_name(
+        client: *mut jack_client_t,
+        intclient: jack_intclient_t,
+    ) -> *mut libc::c_char = ptr::null_mut();
+    fn jack_get_ports(
+        client: *mut jack_client_t,
+        port_name_pattern: *const libc::c_char,
+        type_name_pattern: *const libc::c_char,
+        flags: libc::c_ulong,
+    ) -> *mut *const libc::c_char = ptr::null_mut();
+    fn jack_get_properties(subject: jack_uuid_t, desc: *mut jack_description_t) -> libc::c_int = -1;
+    fn jack_get_property(
+        subject: jack_uuid_t,
+        key: *const libc::c_char,
+        value: *mut *mut libc::c_char,
+        _type: *mut *mut libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_get_sample_rate(arg1: *mut jack_client_t) -> jack_nframes_t = 0;
+    fn jack_get_uuid_for_client_name(
+        client: *mut jack_client_t,
+        client_name: *const libc::c_char,
+    ) -> *mut libc::c_char = ptr::null_mut();
+    fn jack_get_xrun_delayed_usecs(client: *mut jack_client_t) -> libc::c_float = 0.0;
+    fn jack_internal_client_handle(
+        client: *mut jack_client_t,
+        client_name: *const libc::c_char,
+        status: *mut jack_status_t,
+    ) -> jack_intclient_t = 0;
+    fn jack_internal_client_unload(
+        client: *mut jack_client_t,
+        intclient: jack_intclient_t,
+    ) -> jack_status_t = JackFailure;
+    fn jack_is_realtime(client: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_last_frame_time(client: *const jack_client_t) -> jack_nframes_t = 0;
+    fn jack_midi_clear_buffer(port_buffer: *mut libc::c_void) -> () = ();
+    fn jack_midi_event_get(
+        event: *mut jack_midi_event_t,
+        port_buffer: *mut libc::c_void,
+        event_index: u32,
+    ) -> libc::c_int = -1;
+    fn jack_midi_event_write(
+        port_buffer: *mut libc::c_void,
+        time: jack_nframes_t,
+        data: *const jack_midi_data_t,
+        data_size: libc::size_t,
+    ) -> libc::c_int = -1;
+    fn jack_midi_get_event_count(port_buffer: *mut libc::c_void) -> u32 = 0;
+    fn jack_midi_get_lost_event_count(port_buffer: *mut libc::c_void) -> u32 = 0;
+    fn jack_midi_max_event_size(port_buffer: *mut libc::c_void) -> libc::size_t = 0;
+    fn jack_on_shutdown(
+        client: *mut jack_client_t,
+        shutdown_callback: JackShutdownCallback,
+        arg: *mut libc::c_void,
+    ) -> () = ();
+    fn jack_port_by_id(
+        client: *mut jack_client_t,
+        port_id: jack_port_id_t,
+    ) -> *mut jack_port_t = ptr::null_mut();
+    fn jack_port_by_name(
+        client: *mut jack_client_t,
+        port_name: *const libc::c_char,
+    ) -> *mut jack_port_t = ptr::null_mut();
+    fn jack_port_flags(port: *const jack_port_t) -> libc::c_int = -1;
+    fn jack_port_get_aliases(
+        port: *const jack_port_t,
+        aliases: *mut *mut libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_port_get_buffer(
+        port: *mut jack_port_t,
+        arg1: jack_nframes_t,
+    ) -> *mut libc::c_void = ptr::null_mut();
+    fn jack_port_get_latency_range(
+        port: *mut jack_port_t,
+        mode: jack_latency_callback_mode_t,
+        range: *mut jack_latency_range_t,
+    ) -> () = ();
+    fn jack_port_name(port: *const jack_port_t) -> *const libc::c_char = ptr::null();
+    fn jack_port_name_size() -> libc::c_int = -1;
+    fn jack_port_register(
+        client: *mut jack_client_t,
+        port_name: *const libc::c_char,
+        port_type: *const libc::c_char,
+        flags: libc::c_ulong,
+        buffer_size: libc::c_ulong,
+    ) -> *mut jack_port_t = ptr::null_mut();
+    fn jack_port_set_alias(port: *mut jack_port_t, alias: *const libc::c_char) -> libc::c_int = -1;
+    fn jack_port_set_latency_range(
+        port: *mut jack_port_t,
+        mode: jack_latency_callback_mode_t,
+        range: *mut jack_latency_range_t,
+    ) -> () = ();
+    fn jack_port_type_get_buffer_size(
+        client: *mut jack_client_t,
+        port_type: *const libc::c_char,
+    ) -> libc::size_t = 0;
+    fn jack_port_unregister(client: *mut jack_client_t, port: *mut jack_port_t) -> libc::c_int = -1;
+    fn jack_port_unset_alias(
+        port: *mut jack_port_t,
+        alias: *const libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_port_uuid(port: *const jack_port_t) -> jack_uuid_t = 0;
+    fn jack_recompute_total_latencies(client: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_remove_all_properties(client: *mut jack_client_t) -> libc::c_int = -1;
+    fn jack_remove_properties(client: *mut jack_client_t, subject: jack_uuid_t) -> libc::c_int = -1;
+    fn jack_remove_property(
+        client: *mut jack_client_t,
+        subject: jack_uuid_t,
+        key: *const libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_set_error_function(func: Option<unsafe extern "C" fn(*const libc::c_char)>) -> () = ();
+    fn jack_set_freewheel(client: *mut jack_client_t, onoff: libc::c_int) -> libc::c_int = -1;
+    fn jack_set_freewheel_callback(
+        client: *mut jack_client_t,
+        freewheel_callback: JackFreewheelCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_info_function(func: Option<unsafe extern "C" fn(*const libc::c_char)>) -> () = ();
+    fn jack_set_latency_callback(
+        client: *mut jack_client_t,
+        latency_callback: JackLatencyCallback,
+        arg1: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_port_connect_callback(
+        client: *mut jack_client_t,
+        connect_callback: JackPortConnectCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_process_callback(
+        client: *mut jack_client_t,
+        process_callback: JackProcessCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_process_thread(
+        client: *mut jack_client_t,
+        thread_callback: JackThreadCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_property(
+        arg1: *mut jack_client_t,
+        subject: jack_uuid_t,
+        key: *const libc::c_char,
+        value: *const libc::c_char,
+        _type: *const libc::c_char,
+    ) -> libc::c_int = -1;
+    fn jack_set_property_change_callback(
+        client: *mut jack_client_t,
+        callback: JackPropertyChangeCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_sample_rate_callback(
+        client: *mut jack_client_t,
+        srate_callback: JackSampleRateCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_thread_init_callback(
+        client: *mut jack_client_t,
+        thread_init_callback: JackThreadInitCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+    fn jack_set_xrun_callback(
+        client: *mut jack_client_t,
+        xrun_callback: JackXRunCallback,
+        arg: *mut libc::c_void,
+    ) -> libc::c_int = -1;
+}
+
+pub(crate) unsafe fn client_open(
+    name: &CStr,
+    opts: jack_options_t,
+    status: *mut jack_status_t,
+    server: Option<&CStr>)
+    -> *mut jack_client_t
+{
+    let f = match symbols().and_then(|s| s.jack_client_open) {
+        Some(f) => f,
+        None    => {
+            if !status.is_null() {
+                *status = JackFailure | ::types::status::LIBRARY_NOT_FOUND.bits();
+            }
+
+            return ptr::null_mut();
+        },
+    };
+
+    match server {
+        Some(s) => f(name.as_ptr(), opts, status, s.as_ptr()),
+        None    => f(name.as_ptr(), opts, status),
+    }
+}
+
+pub(crate) unsafe fn internal_client_load(
+    client: *mut jack_client_t,
+    name: &CStr,
+    opts: jack_options_t,
+    status: *mut jack_status_t,
+    load_name: Option<&CStr>,
+    load_init: Option<&CStr>)
+    -> jack_intclient_t
+{
+    let f = match symbols().and_then(|s| s.jack_internal_client_load) {
+        Some(f) => f,
+        None    => {
+            if !status.is_null() {
+                *status = JackFailure | JackLoadFailure;
+            }
+
+            return 0;
+        },
+    };
+
+    let n = name.as_ptr();
+    match (load_name, load_init) {
+        (Some(l), Some(i)) => f(client, n, opts, status, l.as_ptr(), i.as_ptr()),
+        (Some(l), None)    => f(client, n, opts, status, l.as_ptr()),
+        (None, Some(i))    => f(client, n, opts, status, i.as_ptr()),
+        (None, None)       => f(client, n, opts, status),
+    }
+}
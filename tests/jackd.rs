@@ -0,0 +1,339 @@
+//! Integration tests which run real clients against a `jackd` using the dummy backend. These need
+//! `jackd` on the PATH, so they only run with the `jackd-tests` feature:
+//!
+//!     cargo test --features jackd-tests --test jackd
+//!
+//! Every test starts its own server with a unique name, so they can run in parallel without
+//! touching the default server.
+#![cfg(feature = "jackd-tests")]
+
+extern crate easyjack as jack;
+extern crate nix;
+
+use nix::sys::signal;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 48000;
+const PERIOD: u32 = 256;
+const TIMEOUT_SECS: u64 = 10;
+
+static NEXT_SERVER: AtomicUsize = AtomicUsize::new(0);
+
+/// A `jackd -d dummy` process, which is shut down when dropped
+struct TestServer {
+    name:  String,
+    jackd: Child,
+}
+
+impl TestServer {
+    fn start() -> Self {
+        let name = format!("easyjack-test-{}-{}",
+                           std::process::id(),
+                           NEXT_SERVER.fetch_add(1, Ordering::SeqCst));
+
+        let jackd = Command::new("jackd")
+            .args(&["--no-realtime", "-n", &name, "-d", "dummy"])
+            .args(&["-r", &SAMPLE_RATE.to_string(), "-p", &PERIOD.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("could not run jackd, is it installed?");
+
+        TestServer { name: name, jackd: jackd }
+    }
+
+    /// Opens a client on this server, retrying until the server is ready to accept it
+    fn client(&self, name: &str) -> jack::Client<'static> {
+        let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+        loop {
+            let res = jack::Client::open_connection_to(
+                name, &self.name, jack::options::NO_START_SERVER | jack::options::USE_EXACT_NAME);
+
+            match res {
+                Ok((client, _)) => return client,
+                Err(status) => {
+                    if Instant::now() > deadline {
+                        panic!("could not connect to {}: {:?}", self.name, status);
+                    }
+
+                    thread::sleep(Duration::from_millis(50));
+                },
+            }
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // SIGTERM lets jackd clean up its shared memory, fall back to SIGKILL if it hangs
+        let _ = signal::kill(self.jackd.id() as i32, signal::Signal::SIGTERM);
+
+        let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.jackd.try_wait() {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let _ = self.jackd.kill();
+        let _ = self.jackd.wait();
+    }
+}
+
+fn recv<T>(rx: &Receiver<T>, what: &str) -> T {
+    rx.recv_timeout(Duration::from_secs(TIMEOUT_SECS))
+        .unwrap_or_else(|_| panic!("timed out waiting for {}", what))
+}
+
+type AudioIn  = jack::InputPortHandle<jack::DefaultAudioSample>;
+type AudioOut = jack::OutputPortHandle<jack::DefaultAudioSample>;
+type MidiIn   = jack::InputPortHandle<jack::MidiEvent>;
+type MidiOut  = jack::OutputPortHandle<jack::MidiEvent>;
+
+struct Constant {
+    out:   AudioOut,
+    value: jack::DefaultAudioSample,
+}
+
+impl jack::ProcessHandler for Constant {
+    fn process(&mut self, ctx: &jack::CallbackContext, nframes: jack::NumFrames) -> i32 {
+        for s in self.out.get_write_buffer(nframes, ctx).iter_mut() {
+            *s = self.value;
+        }
+
+        0
+    }
+}
+
+struct Gain {
+    input:  AudioIn,
+    output: AudioOut,
+    gain:   jack::DefaultAudioSample,
+}
+
+impl jack::ProcessHandler for Gain {
+    fn process(&mut self, ctx: &jack::CallbackContext, nframes: jack::NumFrames) -> i32 {
+        let i = self.input.get_read_buffer(nframes, ctx);
+        let o = self.output.get_write_buffer(nframes, ctx);
+        for (o, i) in o.iter_mut().zip(i.iter()) {
+            *o = *i * self.gain;
+        }
+
+        0
+    }
+}
+
+struct AudioCapture {
+    input: AudioIn,
+    tx:    SyncSender<Vec<jack::DefaultAudioSample>>,
+}
+
+impl jack::ProcessHandler for AudioCapture {
+    fn process(&mut self, ctx: &jack::CallbackContext, nframes: jack::NumFrames) -> i32 {
+        // the test only needs a few blocks, drop the rest
+        let _ = self.tx.try_send(self.input.get_read_buffer(nframes, ctx).to_vec());
+        0
+    }
+}
+
+#[test]
+fn audio_passthrough() {
+    let server = TestServer::start();
+
+    let mut source = server.client("source");
+    let out = source.register_output_audio_port("out").unwrap();
+    source.set_process_handler(Constant { out: out, value: 0.25 }).unwrap();
+
+    let mut thru = server.client("thru");
+    let input = thru.register_input_audio_port("in").unwrap();
+    let output = thru.register_output_audio_port("out").unwrap();
+    thru.set_process_handler(Gain { input: input, output: output, gain: 2.0 }).unwrap();
+
+    let (tx, rx) = mpsc::sync_channel(16);
+    let mut sink = server.client("sink");
+    let input = sink.register_input_audio_port("in").unwrap();
+    sink.set_process_handler(AudioCapture { input: input, tx: tx }).unwrap();
+
+    source.activate().unwrap();
+    thru.activate().unwrap();
+    sink.activate().unwrap();
+
+    sink.connect_ports("source:out", "thru:in").unwrap();
+    sink.connect_ports("thru:out", "sink:in").unwrap();
+
+    // blocks captured before the connections were made are silent
+    let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+    loop {
+        let block = recv(&rx, "audio");
+        assert_eq!(block.len(), PERIOD as usize);
+
+        if block.iter().all(|&s| s == 0.5) {
+            break;
+        }
+
+        assert!(block.iter().all(|&s| s == 0.0 || s == 0.5), "unexpected samples: {:?}", block);
+        assert!(Instant::now() < deadline, "signal never arrived");
+    }
+
+    sink.close().unwrap();
+    thru.close().unwrap();
+    source.close().unwrap();
+}
+
+struct NoteSource {
+    out: MidiOut,
+}
+
+impl jack::ProcessHandler for NoteSource {
+    fn process(&mut self, ctx: &jack::CallbackContext, nframes: jack::NumFrames) -> i32 {
+        let mut writer = self.out.get_write_buffer(nframes, ctx);
+        writer.write(10, &[0x90, 60, 100]).unwrap();
+        writer.write(20, &[0x80, 60, 0]).unwrap();
+        0
+    }
+}
+
+struct MidiCapture {
+    input: MidiIn,
+    tx:    SyncSender<Vec<(jack::NumFrames, Vec<u8>)>>,
+}
+
+impl jack::ProcessHandler for MidiCapture {
+    fn process(&mut self, ctx: &jack::CallbackContext, nframes: jack::NumFrames) -> i32 {
+        let buf = self.input.get_read_buffer(nframes, ctx);
+        let events = (0..buf.len())
+            .map(|i| {
+                let e = buf.get(i);
+                (e.get_jack_time(), e.raw_midi_bytes().to_vec())
+            })
+            .collect();
+
+        let _ = self.tx.try_send(events);
+        0
+    }
+}
+
+#[test]
+fn midi_round_trip() {
+    let server = TestServer::start();
+
+    let mut source = server.client("notes");
+    let out = source.register_output_midi_port("out").unwrap();
+    source.set_process_handler(NoteSource { out: out }).unwrap();
+
+    let (tx, rx) = mpsc::sync_channel(16);
+    let mut sink = server.client("sink");
+    let input = sink.register_input_midi_port("in").unwrap();
+    sink.set_process_handler(MidiCapture { input: input, tx: tx }).unwrap();
+
+    source.activate().unwrap();
+    sink.activate().unwrap();
+    sink.connect_ports("notes:out", "sink:in").unwrap();
+
+    let expected = vec![(10, vec![0x90, 60, 100]), (20, vec![0x80, 60, 0])];
+    let deadline = Instant::now() + Duration::from_secs(TIMEOUT_SECS);
+    loop {
+        let events = recv(&rx, "midi");
+        if !events.is_empty() {
+            assert_eq!(events, expected);
+            break;
+        }
+
+        assert!(Instant::now() < deadline, "no midi events arrived");
+    }
+
+    sink.close().unwrap();
+    source.close().unwrap();
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Connect(jack::PortId, jack::PortId, bool),
+    Freewheel(bool),
+    Property(jack::UUID, Option<String>, jack::PropertyChange),
+}
+
+struct Recorder {
+    tx: Sender<Event>,
+}
+
+impl jack::MetadataHandler for Recorder {
+    fn on_port_connect(&mut self, a: jack::PortId, b: jack::PortId,
+                       status: jack::PortConnectStatus)
+    {
+        let connected = match status {
+            jack::PortConnectStatus::PortsConnected    => true,
+            jack::PortConnectStatus::PortsDisconnected => false,
+        };
+
+        let _ = self.tx.send(Event::Connect(a, b, connected));
+    }
+
+    fn on_freewheel(&mut self, starting: bool) {
+        let _ = self.tx.send(Event::Freewheel(starting));
+    }
+
+    fn on_property_change(&mut self, subject: jack::UUID, key: Option<&str>,
+                          change: jack::PropertyChange)
+    {
+        let _ = self.tx.send(Event::Property(subject, key.map(|k| k.to_string()), change));
+    }
+
+    fn callbacks_of_interest(&self) -> Vec<jack::MetadataHandlers> {
+        vec![
+            jack::MetadataHandlers::PortConnect,
+            jack::MetadataHandlers::Freewheel,
+            jack::MetadataHandlers::PropertyChange,
+        ]
+    }
+}
+
+#[test]
+fn metadata_callbacks() {
+    use jack::Port;
+
+    let server = TestServer::start();
+
+    let (tx, rx) = mpsc::channel();
+    let mut client = server.client("watcher");
+    let out = client.register_output_audio_port("out").unwrap();
+    let input = client.register_input_audio_port("in").unwrap();
+    client.set_metadata_handler(Recorder { tx: tx }).unwrap();
+    client.activate().unwrap();
+
+    client.connect_ports("watcher:out", "watcher:in").unwrap();
+    match recv(&rx, "port connect") {
+        Event::Connect(a, b, true) => {
+            let a = client.get_port_by_id(a).unwrap().get_name();
+            let b = client.get_port_by_id(b).unwrap().get_name();
+            assert_eq!((a.as_str(), b.as_str()), ("watcher:out", "watcher:in"));
+        },
+        e => panic!("unexpected event {:?}", e),
+    }
+
+    client.disconnect_ports("watcher:out", "watcher:in").unwrap();
+    match recv(&rx, "port disconnect") {
+        Event::Connect(_, _, false) => (),
+        e => panic!("unexpected event {:?}", e),
+    }
+
+    let subject = out.get_uuid();
+    client.set_property(subject, "http://easyjack.test/key", "value", None).unwrap();
+    assert_eq!(recv(&rx, "property change"),
+               Event::Property(subject, Some("http://easyjack.test/key".to_string()),
+                               jack::PropertyChange::Created));
+
+    client.set_freewheel(true).unwrap();
+    assert_eq!(recv(&rx, "freewheel start"), Event::Freewheel(true));
+    client.set_freewheel(false).unwrap();
+    assert_eq!(recv(&rx, "freewheel stop"), Event::Freewheel(false));
+
+    client.unregister_port(input).unwrap();
+    client.close().unwrap();
+}